@@ -0,0 +1,233 @@
+//! Revenue splits and the sqlite record of each distribution.
+//!
+//! Splits are named (`primary`, `secondary`) in a toml file, each listing
+//! its recipients with a weight. An amount is divided in lamports in
+//! proportion to the weights, rounding down, and whatever rounding leaves
+//! over goes to the split's `remainder` recipient so the shares always add
+//! up to the amount exactly.
+
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashMap, error::Error, fs};
+
+/// Transfer status for a share paid to the wallet it is sent from, which
+/// is left where it is rather than sent to itself.
+pub const KEPT: &str = "kept";
+
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct SplitConfig(HashMap<String, Split>);
+
+impl SplitConfig {
+    pub fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let config: SplitConfig = toml::from_str(&fs::read_to_string(path)?)?;
+        for (name, split) in &config.0 {
+            split.validate().map_err(|e| format!("{}: {}", name, e))?;
+        }
+        Ok(config)
+    }
+
+    pub fn split(&self, name: &str) -> Result<&Split, Box<dyn Error>> {
+        self.0
+            .get(name)
+            .ok_or_else(|| format!("no split named {:?}", name).into())
+    }
+
+    /// The label `address` goes by in any split.
+    pub fn label_of(&self, address: &str) -> Option<&str> {
+        self.0
+            .values()
+            .flat_map(|split| &split.recipients)
+            .find(|r| r.address == address)
+            .map(|r| r.label.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Split {
+    /// Label of the recipient that takes the rounding remainder, by default
+    /// the one with the largest weight.
+    pub remainder: Option<String>,
+    pub recipients: Vec<Recipient>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Recipient {
+    pub label: String,
+    pub address: String,
+    pub weight: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Share {
+    pub label: String,
+    pub address: Pubkey,
+    pub lamports: u64,
+}
+
+impl Split {
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.recipients.is_empty() {
+            return Err("no recipients".into());
+        }
+        if self.recipients.iter().all(|r| r.weight == 0) {
+            return Err("all weights are zero".into());
+        }
+        for recipient in &self.recipients {
+            recipient
+                .address
+                .parse::<Pubkey>()
+                .map_err(|e| format!("{} {}: {}", recipient.label, recipient.address, e))?;
+        }
+        if let Some(label) = &self.remainder {
+            if !self.recipients.iter().any(|r| &r.label == label) {
+                return Err(format!("remainder recipient {:?} is not in the split", label).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Index of the recipient that takes the rounding remainder.
+    pub fn remainder_index(&self) -> usize {
+        match &self.remainder {
+            Some(label) => self.recipients.iter().position(|r| &r.label == label),
+            None => None,
+        }
+        .unwrap_or_else(|| {
+            // first of the heaviest, so ties go to whoever is listed first
+            let max = self.recipients.iter().map(|r| r.weight).max().unwrap_or(0);
+            self.recipients
+                .iter()
+                .position(|r| r.weight == max)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Divides `lamports` between the recipients, returning each share and
+    /// the rounding remainder, which is already included in the share of
+    /// the remainder recipient.
+    pub fn shares(&self, lamports: u64) -> Result<(Vec<Share>, u64), Box<dyn Error>> {
+        let total_weight: u128 = self.recipients.iter().map(|r| r.weight as u128).sum();
+        let mut shares = self
+            .recipients
+            .iter()
+            .map(|r| {
+                Ok(Share {
+                    label: r.label.clone(),
+                    address: r.address.parse()?,
+                    lamports: (lamports as u128 * r.weight as u128 / total_weight) as u64,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let remainder = lamports - shares.iter().map(|s| s.lamports).sum::<u64>();
+        shares[self.remainder_index()].lamports += remainder;
+
+        assert_eq!(shares.iter().map(|s| s.lamports).sum::<u64>(), lamports);
+        Ok((shares, remainder))
+    }
+}
+
+/// A share as recorded in `distribution_transfers`.
+#[derive(Clone, Debug)]
+pub struct Transfer {
+    pub label: String,
+    pub recipient: String,
+    pub lamports: u64,
+    pub batch: i64,
+    pub status: String,
+}
+
+pub fn read_transfers(db: &Connection, distribution: i64) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let mut stmt = db.prepare(
+        "SELECT label, recipient, lamports, batch, status
+             FROM distribution_transfers
+             WHERE distribution_id = ?1
+             ORDER BY position",
+    )?;
+    let transfers = stmt
+        .query_map(params![distribution], |row| {
+            Ok(Transfer {
+                label: row.get(0)?,
+                recipient: row.get(1)?,
+                lamports: row.get::<_, i64>(2)? as u64,
+                batch: row.get(3)?,
+                status: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(transfers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "Fzba5Rx6zZHzeUs7XQxaBAxMYE55qaJ8ptrobSSuPzKk";
+    const B: &str = "4PC3jH8txRfFfa2n9AtuQMY3CZ2e3Bq8sbHEG784Zp6C";
+    const C: &str = "H6GSnttdzaY9xuNcCD6uQf3tdwWKJoiWHEy5xoQCdi4A";
+
+    fn split(remainder: Option<&str>, weights: &[u64]) -> Split {
+        Split {
+            remainder: remainder.map(str::to_string),
+            recipients: [A, B, C]
+                .iter()
+                .zip(["a", "b", "c"])
+                .zip(weights)
+                .map(|((address, label), weight)| Recipient {
+                    label: label.to_string(),
+                    address: address.to_string(),
+                    weight: *weight,
+                })
+                .collect(),
+        }
+    }
+
+    fn lamports(shares: &[Share]) -> Vec<u64> {
+        shares.iter().map(|s| s.lamports).collect()
+    }
+
+    #[test]
+    fn remainder_goes_to_the_first_heaviest_by_default() {
+        let (shares, remainder) = split(None, &[1, 2, 2]).shares(101).unwrap();
+        assert_eq!(lamports(&shares), vec![20, 41, 40]);
+        assert_eq!(remainder, 1);
+    }
+
+    #[test]
+    fn remainder_goes_to_the_named_recipient() {
+        let split = split(Some("c"), &[1, 1, 1]);
+        assert_eq!(split.remainder_index(), 2);
+        let (shares, remainder) = split.shares(100).unwrap();
+        assert_eq!(lamports(&shares), vec![33, 33, 34]);
+        assert_eq!(remainder, 1);
+    }
+
+    #[test]
+    fn shares_add_up_to_the_amount() {
+        let split = split(Some("b"), &[27690, 50770, 21540]);
+        for amount in [0, 1, 15_700_000, 16_493_700_000, 999_999_999_999] {
+            let (shares, remainder) = split.shares(amount).unwrap();
+            assert_eq!(shares.iter().map(|s| s.lamports).sum::<u64>(), amount);
+            assert!(remainder < split.recipients.len() as u64);
+        }
+    }
+
+    #[test]
+    fn repo_splits_are_valid() {
+        let config: SplitConfig = toml::from_str(include_str!("../../splits.toml")).unwrap();
+        for split in config.0.values() {
+            split.validate().unwrap();
+        }
+        let secondary = config.split("secondary").unwrap();
+        let remainder = &secondary.recipients[secondary.remainder_index()];
+        assert_eq!(remainder.label, "exiled custody");
+    }
+
+    #[test]
+    fn rejects_an_unknown_remainder_recipient() {
+        assert!(split(Some("d"), &[1, 1, 1]).validate().is_err());
+        assert!(split(None, &[0, 0, 0]).validate().is_err());
+    }
+}
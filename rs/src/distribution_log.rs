@@ -0,0 +1,263 @@
+//! Reading the distributions that were paid out by hand, before
+//! `distribute`, from the generated `d*.sh` scripts and the console
+//! captures of running them.
+//!
+//! A script lists its `solana transfer` lines in the order they ran. When a
+//! run failed part way, the lines that had already gone through were
+//! commented out and the script run again, so commented and active lines
+//! together, in script order, line up with the `Signature:` lines of the
+//! log in log order.
+
+use std::{error::Error, fs, path::Path};
+
+#[derive(Debug)]
+pub struct LoggedDistribution {
+    pub amount_lamports: u64,
+    pub source: Option<String>,
+    pub balance_before: Option<u64>,
+    pub balance_after: Option<u64>,
+    pub transfers: Vec<LoggedTransfer>,
+}
+
+#[derive(Debug)]
+pub struct LoggedTransfer {
+    pub recipient: String,
+    pub lamports: u64,
+    pub signature: String,
+}
+
+/// Reads the script at `script` and the log next to it with the same stem.
+pub fn read_distribution(script: &Path) -> Result<LoggedDistribution, Box<dyn Error>> {
+    let log = script.with_extension("log");
+    let script_text = fs::read_to_string(script)?;
+    let log_text = fs::read_to_string(&log)?;
+
+    let mut amount_lamports = None;
+    let mut transfers = vec![];
+    for line in script_text.lines() {
+        let line = line.trim().trim_start_matches('#').trim();
+        if let Some(amount) = line
+            .strip_prefix("echo distributing ")
+            .and_then(|rest| rest.strip_suffix(" SOL from"))
+        {
+            amount_lamports = Some(parse_sol(amount)?);
+        } else if line.starts_with("solana transfer ") {
+            transfers.push(parse_transfer(line)?);
+        }
+    }
+
+    let mut source = None;
+    let mut balance_before = None;
+    let mut balance_after = None;
+    let mut signatures = vec![];
+    let mut lines = log_text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if line.starts_with("distributing ") {
+            source = lines.next().map(str::to_string);
+        } else if line == "total available before distribution" {
+            // a run that failed before sending anything is run again, so
+            // the balance that counts is the last one before any transfer
+            let balance = parse_balance(lines.next())?;
+            if signatures.is_empty() {
+                balance_before = Some(balance);
+            }
+        } else if line == "total available after distribution" {
+            balance_after = Some(parse_balance(lines.next())?);
+        } else if let Some(sig) = line.strip_prefix("Signature: ") {
+            signatures.push(sig.trim().to_string());
+        }
+    }
+
+    if signatures.len() != transfers.len() {
+        return Err(format!(
+            "{} has {} transfers but {} has {} signatures",
+            script.display(),
+            transfers.len(),
+            log.display(),
+            signatures.len()
+        )
+        .into());
+    }
+
+    Ok(LoggedDistribution {
+        amount_lamports: amount_lamports
+            .ok_or_else(|| format!("{} never says what it distributes", script.display()))?,
+        source,
+        balance_before,
+        balance_after,
+        transfers: transfers
+            .into_iter()
+            .zip(signatures)
+            .map(|((recipient, lamports), signature)| LoggedTransfer {
+                recipient,
+                lamports,
+                signature,
+            })
+            .collect(),
+    })
+}
+
+/// Recipient and amount of a `solana transfer` command line.
+fn parse_transfer(line: &str) -> Result<(String, u64), Box<dyn Error>> {
+    let mut positional = vec![];
+    let mut words = line.split_whitespace().skip(2);
+    while let Some(word) = words.next() {
+        match word {
+            "-k" | "--keypair" | "-u" | "--url" | "--from" | "--fee-payer" => {
+                words.next();
+            }
+            flag if flag.starts_with('-') => {}
+            arg => positional.push(arg),
+        }
+    }
+    match positional.as_slice() {
+        [recipient, amount] => Ok((recipient.to_string(), parse_sol(amount)?)),
+        _ => Err(format!("can't read transfer {:?}", line).into()),
+    }
+}
+
+fn parse_balance(line: Option<&str>) -> Result<u64, Box<dyn Error>> {
+    let line = line.ok_or("log ends before the balance")?;
+    parse_sol(line.trim_end_matches(" SOL"))
+}
+
+/// Parses a decimal SOL amount to lamports without going through a float.
+pub fn parse_sol(amount: &str) -> Result<u64, Box<dyn Error>> {
+    parse_units(amount, 9)
+}
+
+/// Parses a decimal amount of a token with `decimals` places to its
+/// smallest unit, without going through a float.
+pub fn parse_units(amount: &str, decimals: u8) -> Result<u64, Box<dyn Error>> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty()
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(format!("{:?} is not an amount", amount).into());
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format!("{} has more than {} decimal places", amount, decimals).into());
+    }
+    let too_big = || format!("{} is more than fits in a u64", amount);
+    let unit = 10u64.checked_pow(decimals as u32).ok_or_else(too_big)?;
+    let whole: u64 = whole.parse().map_err(|_| too_big())?;
+    let fraction: u64 = match fraction {
+        "" => 0,
+        fraction => format!("{:0<width$}", fraction, width = decimals as usize).parse()?,
+    };
+    whole
+        .checked_mul(unit)
+        .and_then(|units| units.checked_add(fraction))
+        .ok_or_else(|| too_big().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn script(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(path)
+    }
+
+    #[test]
+    fn parses_sol_exactly() {
+        assert_eq!(parse_sol("30").unwrap(), 30_000_000_000);
+        assert_eq!(parse_sol("0.0157").unwrap(), 15_700_000);
+        assert_eq!(parse_sol("16.4937").unwrap(), 16_493_700_000);
+        assert_eq!(parse_sol("0.000000001").unwrap(), 1);
+        assert_eq!(parse_sol("109.18941504").unwrap(), 109_189_415_040);
+    }
+
+    #[test]
+    fn rejects_what_isnt_an_amount() {
+        for amount in ["", ".5", "-1", "+1", "1e9", "0.0000000001", "1.2.3"] {
+            assert!(parse_sol(amount).is_err(), "{:?}", amount);
+        }
+        assert!(parse_sol("18446744074").is_err());
+    }
+
+    #[test]
+    fn parses_token_amounts_by_their_decimals() {
+        assert_eq!(parse_units("1", 0).unwrap(), 1);
+        assert_eq!(parse_units("2.5", 6).unwrap(), 2_500_000);
+        assert_eq!(parse_units("0.000001", 6).unwrap(), 1);
+        assert_eq!(parse_units("18446744073709.551615", 6).unwrap(), u64::MAX);
+        assert!(parse_units("0.5", 0).is_err());
+        assert!(parse_units("0.0000001", 6).is_err());
+        assert!(parse_units("18446744073709.551616", 6).is_err());
+        for amount in ["-1", "inf", "NaN", "1e3", " 1"] {
+            assert!(parse_units(amount, 6).is_err(), "{:?}", amount);
+        }
+    }
+
+    #[test]
+    fn parses_transfer_lines() {
+        let line = "solana transfer -k ~/keys/exiled-custody/exiled-custody.json \
+                    -u mainnet-beta Fzba5Rx6zZHzeUs7XQxaBAxMYE55qaJ8ptrobSSuPzKk 15.00000 \
+                    --allow-unfunded-recipient";
+        assert_eq!(
+            parse_transfer(line).unwrap(),
+            (
+                "Fzba5Rx6zZHzeUs7XQxaBAxMYE55qaJ8ptrobSSuPzKk".to_string(),
+                15_000_000_000
+            )
+        );
+        assert!(parse_transfer("solana transfer -k key.json 15").is_err());
+    }
+
+    #[test]
+    fn reads_the_primary_sale_log() {
+        let distribution = read_distribution(&script("primary-sale/distributions/d1.sh")).unwrap();
+        assert_eq!(distribution.amount_lamports, 30_000_000_000);
+        assert_eq!(distribution.balance_before, Some(47_457_950_720));
+        assert_eq!(distribution.balance_after, Some(32_457_940_720));
+        assert_eq!(distribution.transfers.len(), 2);
+        assert_eq!(
+            distribution.transfers[1].recipient,
+            "4PC3jH8txRfFfa2n9AtuQMY3CZ2e3Bq8sbHEG784Zp6C"
+        );
+        assert_eq!(distribution.transfers[1].lamports, 15_000_000_000);
+    }
+
+    #[test]
+    fn pairs_commented_transfers_with_their_signatures() {
+        let distribution =
+            read_distribution(&script("secondary-sales/distributions/d1.sh")).unwrap();
+        assert_eq!(
+            distribution.source.as_deref(),
+            Some("4PC3jH8txRfFfa2n9AtuQMY3CZ2e3Bq8sbHEG784Zp6C")
+        );
+        assert_eq!(distribution.balance_before, Some(109_189_415_040));
+        assert_eq!(distribution.balance_after, Some(101_069_560_040));
+
+        let transfers: Vec<_> = distribution
+            .transfers
+            .iter()
+            .map(|t| (t.recipient.as_str(), t.lamports, &t.signature[..4]))
+            .collect();
+        assert_eq!(
+            transfers,
+            vec![
+                (
+                    "Fzba5Rx6zZHzeUs7XQxaBAxMYE55qaJ8ptrobSSuPzKk",
+                    4_567_100_000,
+                    "vVNK"
+                ),
+                (
+                    "4PC3jH8txRfFfa2n9AtuQMY3CZ2e3Bq8sbHEG784Zp6C",
+                    8_373_850_000,
+                    "5fL4"
+                ),
+                (
+                    "H6GSnttdzaY9xuNcCD6uQf3tdwWKJoiWHEy5xoQCdi4A",
+                    3_552_740_000,
+                    "4Jo3"
+                ),
+            ]
+        );
+    }
+}
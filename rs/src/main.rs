@@ -1,15 +1,37 @@
-use borsh::de::BorshDeserialize;
+mod distribute;
+mod distribution_log;
+mod plan;
+mod refund;
+
+use distribute::{read_transfers, SplitConfig, KEPT};
+use distribution_log::{parse_sol, parse_units, read_distribution};
 use gumdrop::Options;
 use mpl_token_metadata::instruction::update_metadata_accounts;
-use mpl_token_metadata::state::{Creator, Data, Metadata};
-use rusqlite::Connection;
+use plan::{read_plan, PlanRow};
+use refund::{
+    pending_refunds, read_refund_list, record_refund, sync_refunds, RefundItem, PAID_EXTERNALLY,
+    REFUND_JOB,
+};
+use rusqlite::{params, Connection, OptionalExtension};
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::account::ReadableAccount;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::program_pack::Pack;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::read_keypair_file;
+use solana_sdk::signature::{read_keypair_file, Keypair};
 use solana_sdk::signer::Signer;
-use solana_sdk::transaction::Transaction;
+use solana_sdk::system_instruction;
 use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use xapes_core::entangler::{create_entangled_pair_instructions, EntanglementTerms};
+use xapes_core::holders::find_holder;
+use xapes_core::journal::{self, Journal, Outcome};
+use xapes_core::metadata::{fetch_metadata, print_data_diff};
+use xapes_core::pda::{find_entangled_pair_address, find_metadata_address};
+use xapes_core::policy::{validate_data, CollectionPolicy, RoyaltyPolicy};
+use xapes_core::schema;
+use xapes_core::sender::Sender;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -17,187 +39,948 @@ async fn main() -> Result<(), Box<dyn Error>> {
     match args.clone().command {
         None => todo!(),
         Some(command) => match command {
-            Command::RescueJoey(opts) => rescue_joey(args, opts).await,
-            Command::UpdateCreatorsAndRoyalties(opts) => {
-                update_creators_and_royalties(args, opts).await
-            }
+            Command::CreateEntanglement(opts) => create_entanglement(args, opts).await,
+            Command::Distribute(opts) => distribute(args, opts).await,
+            Command::ImportDistributionLogs(opts) => import_distribution_logs(args, opts).await,
+            Command::ReconcileRoyalties(opts) => reconcile_royalties(args, opts).await,
+            Command::Refund(opts) => refund(args, opts).await,
+            Command::Report(opts) => report(args, opts).await,
+            Command::UpdateMetadata(opts) => update_metadata(args, opts).await,
         },
     }
 }
-async fn rescue_joey(args: Args, opts: RescueJoey) -> Result<(), Box<dyn Error>> {
-    let rpc = RpcClient::new(args.rpc);
+
+async fn create_entanglement(args: Args, opts: CreateEntanglement) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&args.db)?;
+    let rpc = RpcClient::new(args.rpc.clone());
+    let sender = new_sender(&rpc, &args)?;
     let keypair = read_keypair_file(opts.keypair)?;
-    let _ = keypair;
-
-    let mint_address = "2i9xWCkCN8GKiT5nBbgh8MPrjiMqeggDhULBDYGVVLUw".parse()?;
-    let metadata_address = find_metadata_address(mint_address);
-
-    let metadata = rpc.get_account(&metadata_address)?;
-    let metadata = Metadata::deserialize(&mut metadata.data())?;
-    let data = metadata.data;
-    let creators = data.creators.unwrap();
-
-    if creators.len() == 1 {
-        let new_creators = Some(vec![
-            Creator {
-                address: "GVrbHLmRLTZPCWzQaTEi2DyQCjJEKaxypXJdhE2dTe86".parse()?,
-                verified: false,
-                share: 0,
-            },
-            Creator {
-                address: "Hg5KGxWCwFWCsS5uTbKdjQv6pv21nG5kNwBch3zPKTFq".parse()?,
-                verified: false,
-                share: 65,
-            },
-            Creator {
-                address: "6J81KgSBU1WeURtGk8rGfAfcMzmuinG6xrkns7pgTPxr".parse()?,
-                verified: false,
-                share: 25,
-            },
-            Creator {
-                address: "pitH4RCXUxeS48F9wqk4qTEBDDZtvWhyU6V3WK9ULoM".parse()?,
-                verified: false,
-                share: 5,
-            },
-            Creator {
-                address: "vipreaNkzREYfRD3KfbWLLTcUUEn86jnFGvjDuW38dm".parse()?,
-                verified: false,
-                share: 5,
-            },
-        ]);
 
-        let new_data = Data {
-            creators: new_creators,
-            seller_fee_basis_points: 500,
-            ..data
+    let authority = match opts.authority {
+        Some(authority) => authority.parse()?,
+        None => keypair.pubkey(),
+    };
+    let treasury_mint = match opts.treasury_mint {
+        Some(treasury_mint) => treasury_mint.parse()?,
+        None => spl_token::native_mint::id(),
+    };
+    let price = if treasury_mint == spl_token::native_mint::id() {
+        parse_sol(&opts.price)?
+    } else {
+        let mint = rpc.get_account(&treasury_mint)?;
+        let mint = spl_token::state::Mint::unpack(mint.data())?;
+        parse_units(&opts.price, mint.decimals)?
+    };
+    let terms = EntanglementTerms {
+        authority,
+        treasury_mint,
+        price,
+        pays_every_time: opts.pays_every_time,
+    };
+
+    let journal = Journal::open(&db, opts.job.as_deref().unwrap_or("create-entanglement"))?;
+    journal.resolve_sent(&sender)?;
+
+    for entanglement in read_entanglements(&db)? {
+        let item = &entanglement.mirc_mint_address;
+        if journal.is_done(item)? {
+            continue;
+        }
+
+        // the mono ape is mint a, the mirc ape we hold in custody is mint b
+        let mint_a: Pubkey = entanglement.mono_mint_address.parse()?;
+        let mint_b: Pubkey = entanglement.mirc_mint_address.parse()?;
+        let (entangled_pair, _bump) = find_entangled_pair_address(mint_a, mint_b);
+        journal.plan(
+            item,
+            &format!("entangle {} with {} as {}", mint_a, mint_b, entangled_pair),
+        )?;
+
+        let outcome = if rpc
+            .get_account_with_commitment(&entangled_pair, rpc.commitment())?
+            .value
+            .is_some()
+        {
+            Ok(Outcome::Unchanged)
+        } else {
+            let transfer_authority = Keypair::new();
+            create_entangled_pair_instructions(
+                &keypair.pubkey(),
+                &transfer_authority.pubkey(),
+                mint_a,
+                mint_b,
+                &terms,
+            )
+            .and_then(|instructions| {
+                sender.send_tracked(
+                    &instructions,
+                    &[&keypair, &transfer_authority],
+                    |sig, blockhash| journal.sent(item, sig, blockhash),
+                )
+            })
+            .map(Outcome::from)
         };
+        eprintln!("{} {} {} {:?}", mint_a, mint_b, entangled_pair, outcome);
+        journal.record(item, &outcome)?;
+    }
+
+    journal.finish()
+}
+
+async fn update_metadata(args: Args, opts: UpdateMetadata) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&args.db)?;
+    let rpc = RpcClient::new(args.rpc.clone());
+    let sender = new_sender(&rpc, &args)?;
+    let keypair = read_keypair_file(opts.keypair)?;
+    let journal = Journal::open(&db, opts.job.as_deref().unwrap_or("update-metadata"))?;
+    journal.resolve_sent(&sender)?;
+
+    for row in read_plan(&opts.plan)? {
+        apply_journaled(&journal, &rpc, &sender, &keypair, &row)?;
+    }
+
+    journal.finish()
+}
+
+async fn reconcile_royalties(args: Args, opts: ReconcileRoyalties) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&args.db)?;
+    let rpc = RpcClient::new(args.rpc.clone());
+    let sender = new_sender(&rpc, &args)?;
+    let keypair = read_keypair_file(opts.keypair)?;
+    let policy = RoyaltyPolicy::read(&opts.policy)?;
+    let journal = Journal::open(&db, opts.job.as_deref().unwrap_or("reconcile-royalties"))?;
+    journal.resolve_sent(&sender)?;
+
+    for entanglement in read_entanglements(&db)? {
+        if let Some(mirc) = &policy.mirc {
+            let row = policy_plan_row(&entanglement.mirc_mint_address, mirc);
+            apply_journaled(&journal, &rpc, &sender, &keypair, &row)?;
+        }
+        if let Some(mono) = &policy.mono {
+            let row = policy_plan_row(&entanglement.mono_mint_address, mono);
+            apply_journaled(&journal, &rpc, &sender, &keypair, &row)?;
+        }
+    }
+
+    journal.finish()
+}
+
+/// Transfers that go out in one transaction. A system transfer costs 33
+/// bytes of instruction and a 32 byte account key, so 15 leave room under
+/// the packet limit.
+const TRANSFERS_PER_TRANSACTION: usize = 15;
+
+const LAMPORTS_PER_SIGNATURE: u64 = 5000;
 
-        let instructions = vec![update_metadata_accounts(
-            mpl_token_metadata::id(),
-            metadata_address,
-            keypair.pubkey(),
-            None,
-            Some(new_data),
-            None,
-        )];
-
-        let signing_keypairs = &[&keypair];
-        let recent_blockhash = rpc.get_latest_blockhash()?;
-        let tx = Transaction::new_signed_with_payer(
-            &instructions,
-            Some(&keypair.pubkey()),
-            signing_keypairs,
-            recent_blockhash,
+async fn distribute(args: Args, opts: Distribute) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&args.db)?;
+    let rpc = RpcClient::new(args.rpc.clone());
+    let sender = new_sender(&rpc, &args)?;
+    let keypair = read_keypair_file(&opts.keypair)?;
+    let source = keypair.pubkey();
+
+    if let Some(id) = opts.resume {
+        return send_distribution(&db, &rpc, &sender, &keypair, id);
+    }
+
+    let config = SplitConfig::read(&opts.config)?;
+    let split = config.split(&opts.split)?;
+    let amount = parse_sol(opts.amount.as_deref().ok_or("--amount is required")?)?;
+    let (shares, remainder) = split.shares(amount)?;
+
+    eprintln!(
+        "distributing {} SOL of {} sales from {}",
+        lamports_to_sol(amount),
+        opts.split,
+        source
+    );
+    for share in &shares {
+        eprintln!(
+            "  {:<20} {} {:>16} SOL{}",
+            share.label,
+            share.address,
+            lamports_to_sol(share.lamports),
+            if share.address == source {
+                " (kept)"
+            } else {
+                ""
+            }
         );
+    }
+    let remainder_label = &split.recipients[split.remainder_index()].label;
+    eprintln!(
+        "  rounding remainder of {} lamports goes to {}",
+        remainder, remainder_label
+    );
 
-        let sig = rpc.send_transaction(&tx)?;
-        eprintln!("{} {}", mint_address.to_string(), sig);
+    let outgoing: Vec<_> = shares.iter().filter(|s| s.address != source).collect();
+    let batches = (outgoing.len() + TRANSFERS_PER_TRANSACTION - 1) / TRANSFERS_PER_TRANSACTION;
+    let fees = batches as u64 * LAMPORTS_PER_SIGNATURE;
+    let balance_before = rpc.get_balance(&source)?;
+    eprintln!("  balance before {} SOL", lamports_to_sol(balance_before));
+    if balance_before < amount + fees {
+        return Err(format!(
+            "{} holds {} SOL, short of {} SOL plus {} SOL in fees",
+            source,
+            lamports_to_sol(balance_before),
+            lamports_to_sol(amount),
+            lamports_to_sol(fees)
+        )
+        .into());
     }
 
-    Ok(())
+    if sender.dry_run() {
+        for batch in outgoing.chunks(TRANSFERS_PER_TRANSACTION) {
+            let instructions: Vec<_> = batch
+                .iter()
+                .map(|s| system_instruction::transfer(&source, &s.address, s.lamports))
+                .collect();
+            sender.send(&instructions, &[&keypair])?;
+        }
+        return Ok(());
+    }
+
+    db.execute(
+        "INSERT INTO distributions (
+             sale_type, source, amount_lamports, remainder_lamports, remainder_label,
+             balance_before, origin, created_at
+        )
+        values (?1, ?2, ?3, ?4, ?5, ?6, 'distribute', datetime('now'))",
+        params![
+            opts.split,
+            source.to_string(),
+            amount as i64,
+            remainder as i64,
+            remainder_label,
+            balance_before as i64
+        ],
+    )?;
+    let id = db.last_insert_rowid();
+
+    let mut batch = 0;
+    let mut in_batch = 0;
+    for (position, share) in shares.iter().enumerate() {
+        let status = if share.address == source {
+            KEPT
+        } else {
+            if in_batch == TRANSFERS_PER_TRANSACTION {
+                batch += 1;
+                in_batch = 0;
+            }
+            in_batch += 1;
+            journal::PLANNED
+        };
+        db.execute(
+            "INSERT INTO distribution_transfers (
+                 distribution_id, position, label, recipient, lamports, batch, status
+            )
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                id,
+                position as i64,
+                share.label,
+                share.address.to_string(),
+                share.lamports as i64,
+                batch,
+                status
+            ],
+        )?;
+    }
+    eprintln!("recorded as distribution {}", id);
+
+    send_distribution(&db, &rpc, &sender, &keypair, id)
 }
 
-async fn update_creators_and_royalties(
+/// Sends the transfers of a recorded distribution that haven't gone out
+/// yet, journaled per transaction under `distribution-<id>`.
+fn send_distribution(
+    db: &Connection,
+    rpc: &RpcClient,
+    sender: &Sender,
+    keypair: &Keypair,
+    id: i64,
+) -> Result<(), Box<dyn Error>> {
+    let source: String = db.query_row(
+        "SELECT source FROM distributions WHERE id = ?1",
+        params![id],
+        |row| row.get(0),
+    )?;
+    if source != keypair.pubkey().to_string() {
+        return Err(format!("distribution {} is paid from {}", id, source).into());
+    }
+
+    let job = format!("distribution-{}", id);
+    let journal = Journal::open(db, &job)?;
+    journal.resolve_sent(sender)?;
+
+    let transfers = read_transfers(db, id)?;
+    let mut batches: Vec<i64> = transfers
+        .iter()
+        .filter(|t| t.status != KEPT)
+        .map(|t| t.batch)
+        .collect();
+    batches.dedup();
+
+    for batch in batches {
+        let item = format!("batch-{}", batch);
+        if journal.is_done(&item)? {
+            continue;
+        }
+        let transfers: Vec<_> = transfers
+            .iter()
+            .filter(|t| t.batch == batch && t.status != KEPT)
+            .collect();
+        let instructions = transfers
+            .iter()
+            .map(|t| {
+                Ok(system_instruction::transfer(
+                    &keypair.pubkey(),
+                    &t.recipient.parse()?,
+                    t.lamports,
+                ))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        journal.plan(
+            &item,
+            &format!(
+                "transfer {} SOL to {}",
+                lamports_to_sol(transfers.iter().map(|t| t.lamports).sum()),
+                transfers
+                    .iter()
+                    .map(|t| t.label.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )?;
+        let outcome = sender
+            .send_tracked(&instructions, &[keypair], |sig, blockhash| {
+                journal.sent(&item, sig, blockhash)
+            })
+            .map(Outcome::from);
+        eprintln!("{} {:?}", item, outcome);
+        journal.record(&item, &outcome)?;
+    }
+
+    // each transfer takes the status and signature of its transaction
+    db.execute(
+        "UPDATE distribution_transfers
+             SET (status, signature) = (
+                 SELECT status, signature FROM job_items
+                     WHERE job_name = ?1 AND item_key = 'batch-' || distribution_transfers.batch
+             )
+             WHERE distribution_id = ?2 AND status != ?3",
+        params![job, id, KEPT],
+    )?;
+    let balance_after = rpc.get_balance(&keypair.pubkey())?;
+    db.execute(
+        "UPDATE distributions SET balance_after = ?2 WHERE id = ?1",
+        params![id, balance_after as i64],
+    )?;
+    eprintln!("balance after {} SOL", lamports_to_sol(balance_after));
+
+    journal.finish()
+}
+
+async fn import_distribution_logs(
     args: Args,
-    opts: UpdateCreatorsAndRoyalties,
+    opts: ImportDistributionLogs,
 ) -> Result<(), Box<dyn Error>> {
-    let db = Connection::open(args.db)?;
-    let rpc = RpcClient::new(args.rpc);
-    let keypair = read_keypair_file(opts.keypair)?;
+    let mut db = schema::open(&args.db)?;
+    let rpc = RpcClient::new(args.rpc.clone());
+    let config = SplitConfig::read(&opts.config)?;
 
-    let entanglements = &"SELECT mirc_mint_address, mono_mint_address FROM entanglements";
-    let mut entanglements = db.prepare(entanglements)?;
-    let entanglements = entanglements.query_map([], |row| {
-        Ok(Entanglement {
-            mirc_mint_address: row.get(0)?,
-            mono_mint_address: row.get(0)?,
-        })
-    })?;
+    let dirs = if opts.dirs.is_empty() {
+        vec![
+            "../primary-sale/distributions".to_string(),
+            "../secondary-sales/distributions".to_string(),
+        ]
+    } else {
+        opts.dirs
+    };
+
+    for dir in dirs {
+        let mut scripts = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name.starts_with('d') && name.ends_with(".sh") {
+                scripts.push(path);
+            }
+        }
+        scripts.sort();
+
+        for script in scripts {
+            let (sale_type, origin) = distribution_origin(&script.with_extension("log"))?;
+            let distribution = read_distribution(&script)?;
 
-    for entanglement in entanglements {
-        let entanglement = entanglement?;
-        let mint = entanglement.mirc_mint_address.parse()?;
-        let metadata_address = find_metadata_address(mint);
-        let metadata = rpc.get_account(&metadata_address)?;
-        let metadata = Metadata::deserialize(&mut metadata.data())?;
-
-        let data = metadata.data;
-        let creators = data.creators.unwrap();
-
-        if creators[1].address == "4PC3jH8txRfFfa2n9AtuQMY3CZ2e3Bq8sbHEG784Zp6C".parse()? {
-            let new_creators = Some(vec![
-                creators[0].clone(),
-                Creator {
-                    address: "Hg5KGxWCwFWCsS5uTbKdjQv6pv21nG5kNwBch3zPKTFq".parse()?,
-                    verified: false,
-                    share: 65,
+            // the logs carry no dates, so take the time of the first transfer
+            let block_time = match distribution.transfers.first() {
+                Some(transfer) => match signature_block_time(&rpc, &transfer.signature) {
+                    Ok(time) => time,
+                    Err(e) => {
+                        eprintln!("{} no block time for {}: {}", origin, transfer.signature, e);
+                        None
+                    }
                 },
-                creators[2].clone(),
-                creators[3].clone(),
-                creators[4].clone(),
-            ]);
-
-            let new_data = Data {
-                creators: new_creators,
-                ..data
+                None => None,
             };
 
-            let instructions = vec![update_metadata_accounts(
-                mpl_token_metadata::id(),
-                metadata_address,
-                keypair.pubkey(),
-                None,
-                Some(new_data),
-                None,
-            )];
-
-            let signing_keypairs = &[&keypair];
-            let recent_blockhash = rpc.get_latest_blockhash()?;
-            let tx = Transaction::new_signed_with_payer(
-                &instructions,
-                Some(&keypair.pubkey()),
-                signing_keypairs,
-                recent_blockhash,
+            // importing a log again replaces what it imported before
+            let tx = db.transaction()?;
+            tx.execute(
+                "DELETE FROM distribution_transfers WHERE distribution_id IN (
+                     SELECT id FROM distributions WHERE origin = ?1
+                )",
+                params![origin],
+            )?;
+            tx.execute(
+                "DELETE FROM distributions WHERE origin = ?1",
+                params![origin],
+            )?;
+            tx.execute(
+                "INSERT INTO distributions (
+                     sale_type, source, amount_lamports, balance_before, balance_after,
+                     origin, created_at
+                )
+                values (?1, ?2, ?3, ?4, ?5, ?6, datetime(?7, 'unixepoch'))",
+                params![
+                    sale_type,
+                    distribution.source,
+                    distribution.amount_lamports as i64,
+                    distribution.balance_before.map(|b| b as i64),
+                    distribution.balance_after.map(|b| b as i64),
+                    origin,
+                    block_time
+                ],
+            )?;
+            let id = tx.last_insert_rowid();
+            for (position, transfer) in distribution.transfers.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO distribution_transfers (
+                         distribution_id, position, label, recipient, lamports, batch, status,
+                         signature
+                    )
+                    values (?1, ?2, ?3, ?4, ?5, ?2, ?6, ?7)",
+                    params![
+                        id,
+                        position as i64,
+                        config.label_of(&transfer.recipient),
+                        transfer.recipient,
+                        transfer.lamports as i64,
+                        journal::CONFIRMED,
+                        transfer.signature
+                    ],
+                )?;
+            }
+            tx.commit()?;
+
+            eprintln!(
+                "{} {} SOL of {} sales in {} transfers",
+                origin,
+                lamports_to_sol(distribution.amount_lamports),
+                sale_type,
+                distribution.transfers.len()
             );
+        }
+    }
+    Ok(())
+}
+
+/// The sale type a distribution log belongs to, from the directory it is
+/// in, and its path from that directory on.
+fn distribution_origin(log: &Path) -> Result<(&'static str, String), Box<dyn Error>> {
+    let components: Vec<_> = log.components().collect();
+    for (i, component) in components.iter().enumerate() {
+        let sale_type = match component {
+            Component::Normal(name) if *name == "primary-sale" => "primary",
+            Component::Normal(name) if *name == "secondary-sales" => "secondary",
+            _ => continue,
+        };
+        let origin: PathBuf = components[i..].iter().collect();
+        return Ok((sale_type, origin.display().to_string()));
+    }
+    Err(format!(
+        "{} is not under primary-sale or secondary-sales",
+        log.display()
+    )
+    .into())
+}
+
+fn signature_block_time(rpc: &RpcClient, signature: &str) -> Result<Option<i64>, Box<dyn Error>> {
+    let statuses = rpc.get_signature_statuses_with_history(&[signature.parse()?])?;
+    match statuses.value.into_iter().next().flatten() {
+        Some(status) => Ok(Some(rpc.get_block_time(status.slot)?)),
+        None => Ok(None),
+    }
+}
+
+async fn refund(args: Args, opts: Refund) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&args.db)?;
+    let rpc = RpcClient::new(args.rpc.clone());
+    let items = read_refund_list(&opts.list)?;
+    let lamports = opts.amount.as_deref().map(parse_sol).transpose()?;
+
+    if opts.paid_externally {
+        let mut recorded = 0;
+        for item in &items {
+            recorded += db.execute(
+                "INSERT INTO refunds
+                (mint_address, wallet, lamports, status, updated_at) values
+                (          ?1,     ?2,       ?3,     ?4, datetime('now'))
+                ON CONFLICT (mint_address) DO NOTHING",
+                params![
+                    item.mint,
+                    item.wallet,
+                    lamports.map(|l| l as i64),
+                    PAID_EXTERNALLY
+                ],
+            )?;
+        }
+        eprintln!(
+            "recorded {} refunds as paid, {} were already recorded",
+            recorded,
+            items.len() - recorded
+        );
+        return Ok(());
+    }
+
+    let lamports = lamports.ok_or("--amount is required")?;
+    let keypair = read_keypair_file(opts.keypair.as_ref().ok_or("--keypair is required")?)?;
+    let custody: Option<Pubkey> = opts
+        .custody
+        .as_deref()
+        .map(str::parse::<Pubkey>)
+        .transpose()?;
+    let sender = new_sender(&rpc, &args)?;
+    let journal = Journal::open(&db, REFUND_JOB)?;
+    journal.resolve_sent(&sender)?;
+    sync_refunds(&db)?;
+
+    let pending = pending_refunds(&db, &items)?;
+    let total = lamports * pending.len() as u64;
+    let fees = LAMPORTS_PER_SIGNATURE * pending.len() as u64;
+    let balance = rpc.get_balance(&keypair.pubkey())?;
+    eprintln!(
+        "refunding {} SOL for each of {} mints, {} SOL in all, from {} holding {} SOL",
+        lamports_to_sol(lamports),
+        pending.len(),
+        lamports_to_sol(total),
+        keypair.pubkey(),
+        lamports_to_sol(balance)
+    );
+    if balance < total + fees {
+        return Err(format!(
+            "{} SOL is short of {} SOL plus {} SOL in fees",
+            lamports_to_sol(balance),
+            lamports_to_sol(total),
+            lamports_to_sol(fees)
+        )
+        .into());
+    }
+
+    for item in pending {
+        journal.plan(
+            &item.mint,
+            &format!("refund {} SOL", lamports_to_sol(lamports)),
+        )?;
+        let outcome = refund_wallet(&db, &rpc, item, custody).and_then(|wallet| {
+            pay_refund(&db, &sender, &journal, &keypair, item, wallet, lamports)
+        });
+        eprintln!("{} {:?}", item.mint, outcome);
+        journal.record(&item.mint, &outcome)?;
+        sync_refunds(&db)?;
+    }
+
+    journal.finish()
+}
+
+/// Who is owed the refund of `item`: the wallet the list names, else the
+/// last holder before custody when the mint has to be back in custody,
+/// else its holder now.
+fn refund_wallet(
+    db: &Connection,
+    rpc: &RpcClient,
+    item: &RefundItem,
+    custody: Option<Pubkey>,
+) -> Result<Pubkey, Box<dyn Error>> {
+    let mint: Pubkey = item.mint.parse()?;
+    let holder = find_holder(rpc, &mint)?;
+    if let Some(custody) = custody {
+        match &holder {
+            Some(holder) if holder.owner == custody => {}
+            Some(holder) => {
+                return Err(format!("{} is held by {}, not custody", mint, holder.owner).into())
+            }
+            None => return Err(format!("{} is held by no one", mint).into()),
+        }
+    }
+
+    match (&item.wallet, custody) {
+        (Some(wallet), _) => Ok(wallet.parse()?),
+        (None, Some(custody)) => {
+            let owner: Option<String> = db
+                .query_row(
+                    "SELECT owner FROM holders
+                         WHERE mint_address = ?1 AND owner IS NOT NULL AND owner != ?2
+                         ORDER BY snapshot_id DESC
+                         LIMIT 1",
+                    params![item.mint, custody.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(owner
+                .ok_or_else(|| format!("no snapshot shows who held {} before custody", mint))?
+                .parse()?)
+        }
+        (None, None) => Ok(holder
+            .ok_or_else(|| format!("{} is held by no one", mint))?
+            .owner),
+    }
+}
+
+fn pay_refund(
+    db: &Connection,
+    sender: &Sender,
+    journal: &Journal,
+    keypair: &Keypair,
+    item: &RefundItem,
+    wallet: Pubkey,
+    lamports: u64,
+) -> Result<Outcome, Box<dyn Error>> {
+    record_refund(db, &item.mint, &wallet.to_string(), lamports)?;
+    eprintln!(
+        "{} refunding {} SOL to {}",
+        item.mint,
+        lamports_to_sol(lamports),
+        wallet
+    );
+    let instruction = system_instruction::transfer(&keypair.pubkey(), &wallet, lamports);
+    let sig = sender.send_tracked(&[instruction], &[keypair], |sig, blockhash| {
+        journal.sent(&item.mint, sig, blockhash)
+    })?;
+    Ok(Outcome::from(sig))
+}
+
+async fn report(args: Args, opts: Report) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&args.db)?;
+
+    let mut stmt = db.prepare(
+        "SELECT distributions.sale_type,
+                distribution_transfers.recipient,
+                max(distribution_transfers.label),
+                coalesce(strftime('%Y-%m', distributions.created_at), 'undated'),
+                sum(distribution_transfers.lamports),
+                count(*)
+             FROM distribution_transfers
+             JOIN distributions ON distributions.id = distribution_transfers.distribution_id
+             WHERE distribution_transfers.status IN (?1, ?2)
+                 AND (?3 IS NULL
+                     OR distribution_transfers.recipient = ?3
+                     OR distribution_transfers.label = ?3)
+                 AND (?4 IS NULL OR distributions.sale_type = ?4)
+             GROUP BY 1, 2, 4
+             ORDER BY 2, 1, 4",
+    )?;
+    let rows = stmt
+        .query_map(
+            params![journal::CONFIRMED, KEPT, opts.recipient, opts.sale_type],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, i64>(4)? as u64,
+                    row.get::<_, i64>(5)?,
+                ))
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
 
-            let sig = rpc.send_transaction(&tx)?;
-            eprintln!("{} {}", mint.to_string(), sig);
+    let mut total: Option<(String, String, u64)> = None;
+    for (sale_type, recipient, label, month, lamports, count) in rows {
+        if let Some((total_sale_type, total_recipient, total_lamports)) = &mut total {
+            if *total_sale_type == sale_type && *total_recipient == recipient {
+                *total_lamports += lamports;
+            } else {
+                println!("  total {} SOL\n", lamports_to_sol(*total_lamports));
+                total = None;
+            }
         }
-        break;
+        if total.is_none() {
+            println!(
+                "{} {} ({} sales)",
+                label.as_deref().unwrap_or("-"),
+                recipient,
+                sale_type
+            );
+            total = Some((sale_type, recipient, lamports));
+        }
+        println!(
+            "  {}  {:>16} SOL in {} transfers",
+            month,
+            lamports_to_sol(lamports),
+            count
+        );
+    }
+    if let Some((_, _, total_lamports)) = total {
+        println!("  total {} SOL", lamports_to_sol(total_lamports));
     }
     Ok(())
 }
 
+fn read_entanglements(db: &Connection) -> Result<Vec<Entanglement>, Box<dyn Error>> {
+    let mut stmt = db.prepare(
+        "SELECT mirc_mint_address, mono_mint_address
+             FROM entanglements
+             ORDER BY mirc_mint_address",
+    )?;
+    let entanglements = stmt
+        .query_map([], |row| {
+            Ok(Entanglement {
+                mirc_mint_address: row.get(0)?,
+                mono_mint_address: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(entanglements)
+}
+
+fn policy_plan_row(mint: &str, policy: &CollectionPolicy) -> PlanRow {
+    PlanRow {
+        mint: mint.to_string(),
+        seller_fee_basis_points: Some(policy.seller_fee_basis_points),
+        creators: Some(policy.creators.clone()),
+        ..PlanRow::default()
+    }
+}
+
+fn apply_journaled(
+    journal: &Journal,
+    rpc: &RpcClient,
+    sender: &Sender,
+    keypair: &Keypair,
+    row: &PlanRow,
+) -> Result<(), Box<dyn Error>> {
+    if journal.is_done(&row.mint)? {
+        return Ok(());
+    }
+    journal.plan(&row.mint, "update-metadata")?;
+    let outcome = apply_plan_row(rpc, sender, journal, keypair, row);
+    if let Err(e) = &outcome {
+        eprintln!("{} {}", row.mint, e);
+    }
+    journal.record(&row.mint, &outcome)
+}
+
+fn apply_plan_row(
+    rpc: &RpcClient,
+    sender: &Sender,
+    journal: &Journal,
+    keypair: &Keypair,
+    row: &PlanRow,
+) -> Result<Outcome, Box<dyn Error>> {
+    let mint: Pubkey = row.mint.parse()?;
+    let metadata = fetch_metadata(rpc, mint)?;
+
+    let data = row.apply_to(&metadata.data)?;
+    validate_data(&data).map_err(|e| format!("{} {}", mint, e))?;
+    let new_data = if data != metadata.data {
+        Some(data)
+    } else {
+        None
+    };
+
+    let new_update_authority = match &row.update_authority {
+        Some(update_authority) => {
+            let update_authority: Pubkey = update_authority.parse()?;
+            if update_authority != metadata.update_authority {
+                Some(update_authority)
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    // the metadata program can set primary_sale_happened but never clear it
+    let primary_sale_happened = match row.primary_sale_happened {
+        Some(true) if !metadata.primary_sale_happened => Some(true),
+        Some(false) if metadata.primary_sale_happened => {
+            return Err(format!("{} primary sale has already happened", mint).into());
+        }
+        _ => None,
+    };
+
+    if new_data.is_none() && new_update_authority.is_none() && primary_sale_happened.is_none() {
+        eprintln!("{} up to date", mint);
+        return Ok(Outcome::Unchanged);
+    }
+
+    print_data_diff(
+        &mint,
+        &metadata.data,
+        new_data.as_ref().unwrap_or(&metadata.data),
+    );
+    if let Some(update_authority) = new_update_authority {
+        eprintln!(
+            "  update_authority: {} -> {}",
+            metadata.update_authority, update_authority
+        );
+    }
+    if primary_sale_happened.is_some() {
+        eprintln!("  primary_sale_happened: false -> true");
+    }
+
+    let instruction = update_metadata_accounts(
+        mpl_token_metadata::id(),
+        find_metadata_address(mint),
+        keypair.pubkey(),
+        new_update_authority,
+        new_data,
+        primary_sale_happened,
+    );
+
+    let sig = sender.send_tracked(&[instruction], &[keypair], |sig, blockhash| {
+        journal.sent(&row.mint, sig, blockhash)
+    })?;
+    eprintln!("{} {:?}", mint, sig);
+
+    Ok(Outcome::from(sig))
+}
+
 #[derive(Clone, Debug, Options)]
 struct Args {
     #[options(help = "slite db path")]
     db: String,
     #[options(help = "rpc server", default_expr = "default_rpc_url()", meta = "r")]
     rpc: String,
+    #[options(help = "simulate transactions instead of sending them")]
+    dry_run: bool,
+    #[options(help = "commitment to confirm transactions to", default = "confirmed")]
+    commitment: String,
+    #[options(
+        help = "times to re-sign and resend an expired transaction",
+        default = "5"
+    )]
+    retries: u32,
     #[options(command)]
     command: Option<Command>,
 }
 
+fn new_sender<'a>(rpc: &'a RpcClient, args: &Args) -> Result<Sender<'a>, Box<dyn Error>> {
+    Ok(Sender::new(rpc, args.dry_run)
+        .with_commitment(args.commitment.parse()?)
+        .with_retries(args.retries))
+}
+
 fn default_rpc_url() -> String {
     "https://api.mainnet-beta.solana.com".to_owned()
 }
 
 #[derive(Clone, Debug, Options)]
 enum Command {
-    UpdateCreatorsAndRoyalties(UpdateCreatorsAndRoyalties),
-    RescueJoey(RescueJoey),
+    #[options(help = "create token-entangler pairs for the entanglements table")]
+    CreateEntanglement(CreateEntanglement),
+    #[options(help = "split sale revenue between the recipients in a split config")]
+    Distribute(Distribute),
+    #[options(help = "record the distributions paid out by hand from their scripts and logs")]
+    ImportDistributionLogs(ImportDistributionLogs),
+    #[options(help = "bring every entangled mint in line with the royalty policy")]
+    ReconcileRoyalties(ReconcileRoyalties),
+    #[options(help = "pay a refund to the holder of each mint in a refund list")]
+    Refund(Refund),
+    #[options(help = "total distributions per recipient and sale type by month")]
+    Report(Report),
+    #[options(help = "apply a metadata plan file, changing only fields that differ")]
+    UpdateMetadata(UpdateMetadata),
 }
 
 #[derive(Clone, Debug, Options)]
-struct UpdateCreatorsAndRoyalties {
+struct CreateEntanglement {
     #[options(help = "keypair", meta = "k")]
     keypair: String,
+    #[options(help = "entanglement authority, defaults to keypair")]
+    authority: Option<String>,
+    #[options(
+        help = "swap price in treasury mint units, up to its decimals",
+        default = "1"
+    )]
+    price: String,
+    #[options(help = "charge the swap price on every swap")]
+    pays_every_time: bool,
+    #[options(help = "treasury mint, defaults to SOL")]
+    treasury_mint: Option<String>,
+    #[options(help = "journal job name, defaults to the command name")]
+    job: Option<String>,
 }
 
 #[derive(Clone, Debug, Options)]
-struct RescueJoey {
-    #[options(help = "keypair", meta = "k")]
+struct Distribute {
+    #[options(help = "keypair of the wallet paying out", meta = "k")]
     keypair: String,
+    #[options(help = "split config toml", default = "../splits.toml")]
+    config: String,
+    #[options(help = "split to pay out, primary or secondary", default = "secondary")]
+    split: String,
+    #[options(help = "SOL to distribute")]
+    amount: Option<String>,
+    #[options(help = "send the remaining transfers of a recorded distribution")]
+    resume: Option<i64>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct ImportDistributionLogs {
+    #[options(free, help = "directories of d*.sh scripts and their logs")]
+    dirs: Vec<String>,
+    #[options(
+        help = "split config toml, for recipient labels",
+        default = "../splits.toml"
+    )]
+    config: String,
+}
+
+#[derive(Clone, Debug, Options)]
+struct ReconcileRoyalties {
+    #[options(help = "update authority keypair", meta = "k")]
+    keypair: String,
+    #[options(help = "royalty policy toml", meta = "p")]
+    policy: String,
+    #[options(help = "journal job name, defaults to the command name")]
+    job: Option<String>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct Refund {
+    #[options(help = "keypair of the wallet paying refunds", meta = "k")]
+    keypair: Option<String>,
+    #[options(help = "refund list csv, mint rows or wallet, mint rows")]
+    list: String,
+    #[options(help = "SOL refunded per mint")]
+    amount: Option<String>,
+    #[options(help = "only refund mints this custody wallet holds")]
+    custody: Option<String>,
+    #[options(help = "record the list as refunded already, paying nothing")]
+    paid_externally: bool,
+}
+
+#[derive(Clone, Debug, Options)]
+struct Report {
+    #[options(help = "only this recipient, by address or label")]
+    recipient: Option<String>,
+    #[options(help = "only primary or secondary sales")]
+    sale_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct UpdateMetadata {
+    #[options(help = "update authority keypair", meta = "k")]
+    keypair: String,
+    #[options(help = "plan file, csv or toml", meta = "p")]
+    plan: String,
+    #[options(help = "journal job name, defaults to the command name")]
+    job: Option<String>,
 }
 
 #[derive(Clone, Debug, Options)]
@@ -205,15 +988,3 @@ struct Entanglement {
     mirc_mint_address: String,
     mono_mint_address: String,
 }
-
-fn find_metadata_address(mint: Pubkey) -> Pubkey {
-    let (address, _bump) = Pubkey::find_program_address(
-        &[
-            mpl_token_metadata::state::PREFIX.as_bytes(),
-            mpl_token_metadata::id().as_ref(),
-            mint.as_ref(),
-        ],
-        &mpl_token_metadata::id(),
-    );
-    address
-}
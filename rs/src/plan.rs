@@ -0,0 +1,228 @@
+//! Metadata update plans.
+//!
+//! A plan lists mints and the metadata fields they should end up with. Any
+//! field left out of a row is left as it is on chain. Plans are read from
+//! toml (`[[mints]]` tables) or csv, where creators are written as
+//! `address:share` pairs separated by `;`.
+
+use mpl_token_metadata::state::{Creator, Data};
+use serde::Deserialize;
+use std::{error::Error, fs, fs::File, io::BufReader};
+use xapes_core::policy::CreatorShare;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PlanRow {
+    pub mint: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub uri: Option<String>,
+    pub seller_fee_basis_points: Option<u16>,
+    pub creators: Option<Vec<CreatorShare>>,
+    pub primary_sale_happened: Option<bool>,
+    pub update_authority: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TomlPlan {
+    mints: Vec<PlanRow>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CsvPlanRow {
+    mint: String,
+    name: Option<String>,
+    symbol: Option<String>,
+    uri: Option<String>,
+    seller_fee_basis_points: Option<u16>,
+    creators: Option<String>,
+    primary_sale_happened: Option<bool>,
+    update_authority: Option<String>,
+}
+
+pub fn read_plan(path: &str) -> Result<Vec<PlanRow>, Box<dyn Error>> {
+    if path.ends_with(".toml") {
+        let plan: TomlPlan = toml::from_str(&fs::read_to_string(path)?)?;
+        return Ok(plan.mints);
+    }
+
+    let file = File::open(path)?;
+    let mut rdr = csv::Reader::from_reader(BufReader::new(file));
+    let mut rows = vec![];
+    for result in rdr.deserialize() {
+        let row: CsvPlanRow = result?;
+        let creators = match row.creators {
+            Some(creators) => Some(parse_creators(&creators)?),
+            None => None,
+        };
+        rows.push(PlanRow {
+            mint: row.mint,
+            name: row.name,
+            symbol: row.symbol,
+            uri: row.uri,
+            seller_fee_basis_points: row.seller_fee_basis_points,
+            creators,
+            primary_sale_happened: row.primary_sale_happened,
+            update_authority: row.update_authority,
+        });
+    }
+    Ok(rows)
+}
+
+fn parse_creators(s: &str) -> Result<Vec<CreatorShare>, Box<dyn Error>> {
+    let mut creators = vec![];
+    for creator in s.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+        let (address, share) = creator
+            .split_once(':')
+            .ok_or_else(|| format!("creator {:?} is not address:share", creator))?;
+        creators.push(CreatorShare {
+            address: address.trim().to_string(),
+            share: share.trim().parse()?,
+        });
+    }
+    Ok(creators)
+}
+
+impl PlanRow {
+    /// Returns `current` with this row's fields applied.
+    ///
+    /// Creators that are already verified on chain stay verified; everyone
+    /// else is written unverified, since only the creator can verify itself.
+    pub fn apply_to(&self, current: &Data) -> Result<Data, Box<dyn Error>> {
+        let mut data = current.clone();
+        if let Some(name) = &self.name {
+            data.name = name.clone();
+        }
+        if let Some(symbol) = &self.symbol {
+            data.symbol = symbol.clone();
+        }
+        if let Some(uri) = &self.uri {
+            data.uri = uri.clone();
+        }
+        if let Some(seller_fee_basis_points) = self.seller_fee_basis_points {
+            data.seller_fee_basis_points = seller_fee_basis_points;
+        }
+        if let Some(creators) = &self.creators {
+            let mut new_creators = vec![];
+            for creator in creators {
+                let address = creator.address.parse()?;
+                let verified = current
+                    .creators
+                    .iter()
+                    .flatten()
+                    .any(|c| c.address == address && c.verified);
+                new_creators.push(Creator {
+                    address,
+                    verified,
+                    share: creator.share,
+                });
+            }
+            data.creators = Some(new_creators);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const GVRB: &str = "GVrbHLmRLTZPCWzQaTEi2DyQCjJEKaxypXJdhE2dTe86";
+    const HG5K: &str = "Hg5KGxWCwFWCsS5uTbKdjQv6pv21nG5kNwBch3zPKTFq";
+
+    fn plan(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../plans")
+            .join(name);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn joey() -> Data {
+        Data {
+            name: "Joey".to_string(),
+            symbol: "MIRC".to_string(),
+            uri: "https://arweave.net/joey".to_string(),
+            seller_fee_basis_points: 0,
+            creators: Some(vec![Creator {
+                address: GVRB.parse().unwrap(),
+                verified: true,
+                share: 100,
+            }]),
+        }
+    }
+
+    #[test]
+    fn reads_toml_plans() {
+        let rows = read_plan(&plan("rescue-joey.toml")).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].mint, "2i9xWCkCN8GKiT5nBbgh8MPrjiMqeggDhULBDYGVVLUw");
+        assert_eq!(rows[0].seller_fee_basis_points, Some(500));
+        assert_eq!(rows[0].creators.as_ref().unwrap().len(), 5);
+        assert!(rows[0].name.is_none());
+        assert!(rows[0].uri.is_none());
+    }
+
+    #[test]
+    fn reads_csv_plans_leaving_empty_columns_out() {
+        let rows = read_plan(&plan("ghosts.csv")).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].mint, "C1zuSLjxYW3gSekUAMeSVt74dn826EUHoP1Pfjm8sh5Y");
+        assert!(rows.iter().all(|row| row.uri.is_some()));
+        assert!(rows.iter().all(|row| row.name.is_none()));
+        assert!(rows.iter().all(|row| row.creators.is_none()));
+    }
+
+    #[test]
+    fn parses_csv_creators() {
+        let creators = parse_creators(&format!("{}:65; {}:35;", HG5K, GVRB)).unwrap();
+        assert_eq!(creators.len(), 2);
+        assert_eq!(creators[0].address, HG5K);
+        assert_eq!(creators[0].share, 65);
+        assert_eq!(creators[1].address, GVRB);
+        assert_eq!(creators[1].share, 35);
+        assert!(parse_creators(HG5K).is_err());
+        assert!(parse_creators(&format!("{}:lots", HG5K)).is_err());
+    }
+
+    #[test]
+    fn applies_only_the_fields_a_row_sets() {
+        let row = PlanRow {
+            mint: "2i9xWCkCN8GKiT5nBbgh8MPrjiMqeggDhULBDYGVVLUw".to_string(),
+            uri: Some("https://arweave.net/rescued".to_string()),
+            ..Default::default()
+        };
+        let data = row.apply_to(&joey()).unwrap();
+        assert_eq!(data.uri, "https://arweave.net/rescued");
+        assert_eq!(data.name, "Joey");
+        assert_eq!(data.symbol, "MIRC");
+        assert_eq!(data.seller_fee_basis_points, 0);
+        assert_eq!(data.creators, joey().creators);
+    }
+
+    #[test]
+    fn keeps_verified_creators_verified() {
+        let rows = read_plan(&plan("rescue-joey.toml")).unwrap();
+        let data = rows[0].apply_to(&joey()).unwrap();
+        assert_eq!(data.seller_fee_basis_points, 500);
+        let creators = data.creators.unwrap();
+        assert_eq!(creators.len(), 5);
+        assert_eq!(creators[0].address.to_string(), GVRB);
+        assert!(creators[0].verified);
+        assert_eq!(creators[0].share, 0);
+        assert!(creators[1..].iter().all(|c| !c.verified));
+        assert_eq!(creators.iter().map(|c| c.share as u32).sum::<u32>(), 100);
+    }
+
+    #[test]
+    fn rejects_creators_that_arent_pubkeys() {
+        let row = PlanRow {
+            creators: Some(vec![CreatorShare {
+                address: "nobody".to_string(),
+                share: 100,
+            }]),
+            ..Default::default()
+        };
+        assert!(row.apply_to(&joey()).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Refund lists and the record of refunds paid.
+//!
+//! A list is either rows in the `mono_mints` export shape of
+//! `refunded.csv`, with a `mint_address` column and optionally a `wallet`
+//! one, or the headerless `wallet, mint` rows of `refunded-holders.csv`.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+use xapes_core::journal;
+
+/// The status of a refund paid before the `refund` command existed.
+pub const PAID_EXTERNALLY: &str = "paid externally";
+
+pub const REFUND_JOB: &str = "refund";
+
+#[derive(Clone, Debug)]
+pub struct RefundItem {
+    pub mint: String,
+    /// Who to pay, when the list says.
+    pub wallet: Option<String>,
+}
+
+pub fn read_refund_list(path: &str) -> Result<Vec<RefundItem>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut records = rdr.records();
+    let first = match records.next() {
+        Some(record) => record?,
+        None => return Ok(vec![]),
+    };
+
+    let mut items = vec![];
+    if let Some(mint_column) = first.iter().position(|field| field == "mint_address") {
+        let wallet_column = first.iter().position(|field| field == "wallet");
+        for record in records {
+            let record = record?;
+            let mint = record
+                .get(mint_column)
+                .ok_or_else(|| format!("{} has a row without a mint_address", path))?;
+            items.push(RefundItem {
+                mint: mint.to_string(),
+                wallet: wallet_column
+                    .and_then(|column| record.get(column))
+                    .filter(|wallet| !wallet.is_empty())
+                    .map(str::to_string),
+            });
+        }
+    } else {
+        for record in std::iter::once(Ok(first)).chain(records) {
+            let record = record?;
+            match (record.get(0), record.get(1)) {
+                (Some(wallet), Some(mint)) => items.push(RefundItem {
+                    mint: mint.to_string(),
+                    wallet: Some(wallet.to_string()),
+                }),
+                _ => return Err(format!("{} has a row that isn't wallet, mint", path).into()),
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Whether `mint` has been refunded, or is being: a refund that was sent but
+/// not yet confirmed is settled by the journal before anything else is paid.
+pub fn is_refunded(db: &Connection, mint: &str) -> Result<bool, Box<dyn Error>> {
+    let status: Option<String> = db
+        .query_row(
+            "SELECT status FROM refunds WHERE mint_address = ?1",
+            params![mint],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(matches!(
+        status.as_deref(),
+        Some(journal::CONFIRMED) | Some(journal::SENT) | Some(PAID_EXTERNALLY)
+    ))
+}
+
+/// The items of a list still to be refunded, each mint once.
+pub fn pending_refunds<'a>(
+    db: &Connection,
+    items: &'a [RefundItem],
+) -> Result<Vec<&'a RefundItem>, Box<dyn Error>> {
+    let mut pending: Vec<&RefundItem> = vec![];
+    for item in items {
+        if is_refunded(db, &item.mint)? {
+            eprintln!("{} already refunded", item.mint);
+        } else if !pending.iter().any(|p| p.mint == item.mint) {
+            pending.push(item);
+        }
+    }
+    Ok(pending)
+}
+
+/// Records that `lamports` are about to be paid to `wallet` for `mint`.
+pub fn record_refund(
+    db: &Connection,
+    mint: &str,
+    wallet: &str,
+    lamports: u64,
+) -> Result<(), Box<dyn Error>> {
+    db.execute(
+        "INSERT INTO refunds
+        (mint_address, wallet, lamports, status, updated_at) values
+        (          ?1,     ?2,       ?3,     ?4, datetime('now'))
+        ON CONFLICT (mint_address) DO UPDATE
+        SET wallet = excluded.wallet, lamports = excluded.lamports,
+            status = excluded.status, updated_at = excluded.updated_at",
+        params![mint, wallet, lamports as i64, journal::PLANNED],
+    )?;
+    Ok(())
+}
+
+/// Copies the status and signature of refunds from the journal.
+pub fn sync_refunds(db: &Connection) -> Result<(), Box<dyn Error>> {
+    db.execute(
+        "UPDATE refunds
+             SET (status, signature, updated_at) = (
+                 SELECT status, signature, datetime('now') FROM job_items
+                     WHERE job_name = ?1 AND item_key = refunds.mint_address
+             )
+             WHERE status != ?2
+                 AND mint_address IN (SELECT item_key FROM job_items WHERE job_name = ?1)",
+        params![REFUND_JOB, PAID_EXTERNALLY],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{hash::Hash, signature::Signature};
+    use std::path::Path;
+    use xapes_core::{
+        journal::{Journal, Outcome},
+        schema,
+    };
+
+    const WALLET: &str = "7SrvtjPHapJ964f1bTFvGSdg6kSAX8bgaeCpRZ3zupq8";
+
+    fn item(mint: &str) -> RefundItem {
+        RefundItem {
+            mint: mint.to_string(),
+            wallet: None,
+        }
+    }
+
+    fn refund_status(db: &Connection, mint: &str) -> (String, Option<String>) {
+        db.query_row(
+            "SELECT status, signature FROM refunds WHERE mint_address = ?1",
+            params![mint],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_both_list_shapes() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        let exported = read_refund_list(root.join("refunded.csv").to_str().unwrap()).unwrap();
+        assert_eq!(
+            exported[0].mint,
+            "CvdasGfQyvhraHw7k5B3kAtak8cS1LKgjQEP7TcDwCaK"
+        );
+        assert!(exported.iter().all(|item| item.wallet.is_none()));
+
+        let holders =
+            read_refund_list(root.join("refunded-holders.csv").to_str().unwrap()).unwrap();
+        assert_eq!(
+            holders[0].mint,
+            "CvdasGfQyvhraHw7k5B3kAtak8cS1LKgjQEP7TcDwCaK"
+        );
+        assert_eq!(holders[0].wallet.as_deref(), Some(WALLET));
+    }
+
+    #[test]
+    fn pays_each_mint_once_across_runs() {
+        let mut db = Connection::open_in_memory().unwrap();
+        schema::migrate(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO refunds
+            (mint_address, status) values
+            (   'settled',     ?1)",
+            params![PAID_EXTERNALLY],
+        )
+        .unwrap();
+        let items = vec![
+            item("settled"),
+            item("landed"),
+            item("failed"),
+            item("landed"),
+        ];
+
+        let journal = Journal::open(&db, REFUND_JOB).unwrap();
+        let pending = pending_refunds(&db, &items).unwrap();
+        let mints: Vec<&str> = pending.iter().map(|item| item.mint.as_str()).collect();
+        assert_eq!(mints, ["landed", "failed"]);
+
+        // the run dies after sending the first refund, and the second fails
+        let sig = Signature::new_unique();
+        journal.plan("landed", "refund 0.5 SOL").unwrap();
+        record_refund(&db, "landed", WALLET, 500_000_000).unwrap();
+        journal.sent("landed", &sig, &Hash::new_unique()).unwrap();
+        journal.plan("failed", "refund 0.5 SOL").unwrap();
+        record_refund(&db, "failed", WALLET, 500_000_000).unwrap();
+        journal
+            .record("failed", &Err("insufficient funds".into()))
+            .unwrap();
+        sync_refunds(&db).unwrap();
+        assert_eq!(
+            refund_status(&db, "landed"),
+            (journal::SENT.to_string(), Some(sig.to_string()))
+        );
+        assert_eq!(refund_status(&db, "failed").0, journal::FAILED);
+        assert_eq!(refund_status(&db, "settled").0, PAID_EXTERNALLY);
+
+        // a sent refund is not paid again, a failed one is retried
+        let pending = pending_refunds(&db, &items).unwrap();
+        let mints: Vec<&str> = pending.iter().map(|item| item.mint.as_str()).collect();
+        assert_eq!(mints, ["failed"]);
+
+        journal
+            .record("landed", &Ok(Outcome::Confirmed(sig)))
+            .unwrap();
+        sync_refunds(&db).unwrap();
+        assert_eq!(
+            refund_status(&db, "landed"),
+            (journal::CONFIRMED.to_string(), Some(sig.to_string()))
+        );
+        assert!(is_refunded(&db, "landed").unwrap());
+        assert!(!is_refunded(&db, "failed").unwrap());
+    }
+}
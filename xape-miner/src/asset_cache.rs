@@ -0,0 +1,165 @@
+//! An on-disk cache of everything the loaders fetch: off-chain json,
+//! images and metadata accounts.
+//!
+//! Content is stored once under `blobs/<sha256>`. Each uri has an entry
+//! under `entries/<sha256 of uri>.json` naming the blob it resolved to and
+//! the ETag it was served with, so `--refresh` can revalidate with
+//! `If-None-Match` instead of downloading again. Metadata accounts are
+//! cached under `solana:account:<address>` keys in the same way, and only
+//! fetched again with `--refresh`.
+//!
+//! Offline, nothing is fetched and anything missing from the cache is an
+//! error, so a db can be rebuilt without network from a cache a previous
+//! online run filled.
+
+use crate::rate_limit::RateLimiter;
+use reqwest::{
+    header::{ETAG, IF_NONE_MATCH},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    key: String,
+    sha256: String,
+    etag: Option<String>,
+}
+
+pub struct AssetCache {
+    dir: PathBuf,
+    offline: bool,
+    refresh: bool,
+}
+
+impl AssetCache {
+    pub fn open(dir: &str, offline: bool, refresh: bool) -> Result<Self, Box<dyn Error>> {
+        let dir = Path::new(dir).to_path_buf();
+        fs::create_dir_all(dir.join("blobs"))?;
+        fs::create_dir_all(dir.join("entries"))?;
+        Ok(AssetCache {
+            dir,
+            offline,
+            refresh,
+        })
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn refresh(&self) -> bool {
+        self.refresh
+    }
+
+    /// The body of `uri`, from the cache when it has it.
+    pub async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        limiter: &RateLimiter,
+        uri: &str,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let entry = self.entry(uri)?;
+        match &entry {
+            Some(entry) if self.offline || !self.refresh => return self.read_blob(&entry.sha256),
+            None if self.offline => return Err(format!("{} is not cached", uri).into()),
+            _ => {}
+        }
+
+        limiter.wait().await;
+        let mut request = client.get(uri);
+        if let Some(etag) = entry.as_ref().and_then(|entry| entry.etag.as_ref()) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = &entry {
+                return self.read_blob(&entry.sha256);
+            }
+        }
+        let response = response.error_for_status()?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await?.to_vec();
+        self.put(uri, &body, etag)?;
+        Ok(body)
+    }
+
+    pub fn account(&self, address: &Pubkey) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match self.entry(&account_key(address))? {
+            Some(entry) => Ok(Some(self.read_blob(&entry.sha256)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_account(&self, address: &Pubkey, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.put(&account_key(address), data, None)
+    }
+
+    fn entry(&self, key: &str) -> Result<Option<Entry>, Box<dyn Error>> {
+        let path = self.entry_path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    /// Stores `body` as what `key` resolves to.
+    pub fn put(&self, key: &str, body: &[u8], etag: Option<String>) -> Result<(), Box<dyn Error>> {
+        let sha256 = hex_sha256(body);
+        let blob = self.dir.join("blobs").join(&sha256);
+        if !blob.exists() {
+            write_atomic(&blob, body)?;
+        }
+        let entry = Entry {
+            key: key.to_string(),
+            sha256,
+            etag,
+        };
+        write_atomic(&self.entry_path(key), &serde_json::to_vec_pretty(&entry)?)
+    }
+
+    fn read_blob(&self, sha256: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let body = fs::read(self.dir.join("blobs").join(sha256))?;
+        if hex_sha256(&body) != sha256 {
+            return Err(format!("cached blob {} is corrupt", sha256).into());
+        }
+        Ok(body)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join("entries")
+            .join(format!("{}.json", hex_sha256(key.as_bytes())))
+    }
+}
+
+fn account_key(address: &Pubkey) -> String {
+    format!("solana:account:{}", address)
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Writes through a temporary file so an interrupted run never leaves a
+/// partial blob or entry behind.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
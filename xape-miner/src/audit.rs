@@ -0,0 +1,238 @@
+//! Re-reads every mint we know about and reports where chain has drifted
+//! from what the collection is supposed to look like.
+
+use crate::{
+    candy_machine::{CacheFile, CacheItem},
+    Audit,
+};
+use mpl_token_metadata::state::Metadata;
+use rusqlite::Connection;
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashMap, error::Error, fs::File};
+use xapes_core::{
+    metadata::fetch_metadata_batch,
+    pda::find_entangled_pair_address,
+    policy::{CollectionPolicy, RoyaltyPolicy},
+    schema,
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub collection: String,
+    pub mint: String,
+    pub check: String,
+    pub detail: String,
+}
+
+pub async fn audit(opts: Audit) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+
+    let cache = match &opts.cache {
+        Some(path) => Some(CacheFile::read(path)?.by_name()),
+        None => None,
+    };
+    let policy = match &opts.policy {
+        Some(path) => Some(RoyaltyPolicy::read(path)?),
+        None => None,
+    };
+
+    let mut findings = vec![];
+
+    let mirc_mints = read_mints(&db, "SELECT mint_address FROM mirc_mints")?;
+    let mirc_metadata = fetch_metadata_batch(&rpc, &mirc_mints)?;
+    for (mint, metadata) in mirc_mints.iter().zip(mirc_metadata) {
+        let mut audit = MintAudit::new("mirc", mint, &mut findings);
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => {
+                audit.report("metadata", "no metadata account".to_string());
+                continue;
+            }
+        };
+        audit.check_mirc_name(&metadata);
+        if let Some(cache) = &cache {
+            audit.check_cache_uri(&metadata, cache);
+        }
+        if let Some(mirc) = policy.as_ref().and_then(|p| p.mirc.as_ref()) {
+            audit.check_policy(&metadata, mirc);
+        }
+        audit.check_common(&metadata, !opts.immutable);
+    }
+
+    let mono_mints = read_mints(&db, "SELECT mint_address FROM mono_mints")?;
+    let mono_metadata = fetch_metadata_batch(&rpc, &mono_mints)?;
+    for (mint, metadata) in mono_mints.iter().zip(mono_metadata) {
+        let mut audit = MintAudit::new("mono", mint, &mut findings);
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => {
+                audit.report("metadata", "no metadata account".to_string());
+                continue;
+            }
+        };
+        if let Some(mono) = policy.as_ref().and_then(|p| p.mono.as_ref()) {
+            audit.check_policy(&metadata, mono);
+        }
+        audit.check_common(&metadata, !opts.immutable);
+    }
+
+    check_entangled_pairs(&db, &rpc, &mut findings)?;
+
+    print_findings(&findings);
+    if let Some(path) = &opts.json {
+        serde_json::to_writer_pretty(File::create(path)?, &findings)?;
+    }
+
+    Ok(())
+}
+
+fn read_mints(db: &Connection, sql: &str) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+    let mut stmt = db.prepare(sql)?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut mints = vec![];
+    for row in rows {
+        mints.push(row?.parse()?);
+    }
+    Ok(mints)
+}
+
+/// Every planned pair should have an entangled pair account, with the mono
+/// ape as mint a and the mirc ape as mint b.
+fn check_entangled_pairs(
+    db: &Connection,
+    rpc: &RpcClient,
+    findings: &mut Vec<Finding>,
+) -> Result<(), Box<dyn Error>> {
+    let mut stmt = db.prepare("SELECT mirc_mint_address, mono_mint_address FROM entanglements")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut pairs = vec![];
+    for row in rows {
+        let (mirc_mint, mono_mint) = row?;
+        let (pair, _bump) = find_entangled_pair_address(mono_mint.parse()?, mirc_mint.parse()?);
+        pairs.push((mirc_mint, mono_mint, pair));
+    }
+
+    let addresses: Vec<Pubkey> = pairs.iter().map(|(_, _, pair)| *pair).collect();
+    let mut accounts = vec![];
+    for chunk in addresses.chunks(solana_client::rpc_request::MAX_MULTIPLE_ACCOUNTS) {
+        accounts.extend(rpc.get_multiple_accounts(chunk)?);
+    }
+
+    for ((mirc_mint, mono_mint, pair), account) in pairs.into_iter().zip(accounts) {
+        if account.is_none() {
+            findings.push(Finding {
+                collection: "mirc".to_string(),
+                mint: mirc_mint,
+                check: "entangled_pair".to_string(),
+                detail: format!("no pair account {} with mono {}", pair, mono_mint),
+            });
+        }
+    }
+    Ok(())
+}
+
+struct MintAudit<'a> {
+    collection: &'static str,
+    mint: &'a Pubkey,
+    findings: &'a mut Vec<Finding>,
+}
+
+impl<'a> MintAudit<'a> {
+    fn new(collection: &'static str, mint: &'a Pubkey, findings: &'a mut Vec<Finding>) -> Self {
+        MintAudit {
+            collection,
+            mint,
+            findings,
+        }
+    }
+
+    fn report(&mut self, check: &str, detail: String) {
+        self.findings.push(Finding {
+            collection: self.collection.to_string(),
+            mint: self.mint.to_string(),
+            check: check.to_string(),
+            detail,
+        });
+    }
+
+    /// Mirc apes are named `ExiledApe N/518`.
+    fn check_mirc_name(&mut self, metadata: &Metadata) {
+        let number = metadata
+            .data
+            .name
+            .strip_prefix("ExiledApe ")
+            .and_then(|name| name.strip_suffix("/518"))
+            .and_then(|number| number.parse::<u32>().ok());
+        match number {
+            Some(1..=518) => {}
+            _ => self.report("name", format!("{:?}", metadata.data.name)),
+        }
+    }
+
+    fn check_cache_uri(&mut self, metadata: &Metadata, cache: &HashMap<String, CacheItem>) {
+        match cache.get(&metadata.data.name) {
+            None => self.report("uri", format!("{:?} not in cache", metadata.data.name)),
+            Some(item) if item.link != metadata.data.uri => self.report(
+                "uri",
+                format!("{} but cache has {}", metadata.data.uri, item.link),
+            ),
+            Some(_) => {}
+        }
+    }
+
+    fn check_policy(&mut self, metadata: &Metadata, policy: &CollectionPolicy) {
+        if metadata.data.seller_fee_basis_points != policy.seller_fee_basis_points {
+            self.report(
+                "seller_fee",
+                format!(
+                    "{} but policy has {}",
+                    metadata.data.seller_fee_basis_points, policy.seller_fee_basis_points
+                ),
+            );
+        }
+
+        let actual: Vec<(String, u8)> = metadata
+            .data
+            .creators
+            .iter()
+            .flatten()
+            .map(|c| (c.address.to_string(), c.share))
+            .collect();
+        let expected: Vec<(String, u8)> = policy
+            .creators
+            .iter()
+            .map(|c| (c.address.clone(), c.share))
+            .collect();
+        if actual != expected {
+            self.report("creators", format!("{:?}", actual));
+        }
+    }
+
+    fn check_common(&mut self, metadata: &Metadata, expect_mutable: bool) {
+        for creator in metadata.data.creators.iter().flatten() {
+            if !creator.verified {
+                self.report("unverified_creator", creator.address.to_string());
+            }
+        }
+        if metadata.is_mutable != expect_mutable {
+            self.report("is_mutable", metadata.is_mutable.to_string());
+        }
+    }
+}
+
+pub fn print_findings(findings: &[Finding]) {
+    println!("{:<10} {:<44} {:<20} detail", "collection", "mint", "check");
+    for finding in findings {
+        println!(
+            "{:<10} {:<44} {:<20} {}",
+            finding.collection, finding.mint, finding.check, finding.detail
+        );
+    }
+    eprintln!("{} findings", findings.len());
+}
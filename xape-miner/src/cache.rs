@@ -0,0 +1,168 @@
+//! The candy machine cache in sqlite, and bringing the mirc mints back in
+//! line with it.
+
+use crate::{
+    candy_machine::CacheFile,
+    fixes::{apply_fix, write_fixes, UriFix},
+    new_sender, Args, ImportCache, ReconcileCache,
+};
+use rusqlite::{params, Connection};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::read_keypair_file};
+use std::{collections::HashMap, error::Error, path::Path};
+use xapes_core::{journal::Journal, metadata::fetch_metadata_batch, schema};
+
+pub async fn import_cache(opts: ImportCache) -> Result<(), Box<dyn Error>> {
+    let mut db = schema::open(&opts.db)?;
+    let cache = CacheFile::read(&opts.cache)?;
+
+    // caches are named by the directory they sit in, e.g. mainnet-beta
+    let name = match &opts.name {
+        Some(name) => name.clone(),
+        None => Path::new(&opts.cache)
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .and_then(|name| name.to_str())
+            .ok_or("can't name the cache from its path, pass --name")?
+            .to_string(),
+    };
+
+    let tx = db.transaction()?;
+    tx.execute("DELETE FROM cache_items WHERE cache = ?1", params![name])?;
+    for (index, item) in &cache.items {
+        let index: i64 = index.parse()?;
+        tx.execute(
+            "INSERT INTO cache_items
+            (cache, item_index, name, link, image_link, on_chain) values
+            (   ?1,         ?2,   ?3,   ?4,         ?5,       ?6)",
+            params![
+                name,
+                index,
+                item.name,
+                item.link,
+                item.image_link,
+                item.on_chain
+            ],
+        )?;
+    }
+    tx.commit()?;
+
+    eprintln!(
+        "{} items from {} as {}",
+        cache.items.len(),
+        opts.cache,
+        name
+    );
+    Ok(())
+}
+
+pub async fn reconcile_cache(args: Args, opts: ReconcileCache) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+
+    let links = read_cache_links(&db, &opts.cache)?;
+    if links.is_empty() {
+        return Err(format!("no cache_items for {}, run import-cache first", opts.cache).into());
+    }
+
+    let mut stmt = db.prepare("SELECT mint_address, inmate_number FROM mirc_mints")?;
+    let mirc_mints = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mints = mirc_mints
+        .iter()
+        .map(|(mint, _)| mint.parse())
+        .collect::<Result<Vec<Pubkey>, _>>()?;
+    let metadata = fetch_metadata_batch(&rpc, &mints)?;
+
+    let mut fixes = vec![];
+    let mut found = vec![];
+    let mut unmatched = 0;
+    for (((mint_address, inmate_number), mint), metadata) in
+        mirc_mints.iter().zip(&mints).zip(metadata)
+    {
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => {
+                eprintln!("{} has no metadata account", mint);
+                continue;
+            }
+        };
+
+        // cache items are named for their candy machine index, which has
+        // nothing to do with the inmate number, so only the on-chain name
+        // can find one
+        let name = metadata.data.name.clone();
+        let link = match links.get(&name) {
+            Some(link) => link,
+            None => {
+                eprintln!(
+                    "{} unmatched: no cache entry for {:?}, inmate number {:?}",
+                    mint, name, inmate_number
+                );
+                unmatched += 1;
+                continue;
+            }
+        };
+
+        if &metadata.data.uri == link {
+            continue;
+        }
+        eprintln!(
+            "{} {:?} {} should be {}",
+            mint, name, metadata.data.uri, link
+        );
+        fixes.push(UriFix {
+            mint: mint_address.clone(),
+            uri: link.clone(),
+            name: None,
+        });
+        found.push((*mint, metadata));
+    }
+    eprintln!("{} mints differ from the cache", fixes.len());
+    if unmatched > 0 {
+        eprintln!("{} mints have no cache entry and were skipped", unmatched);
+    }
+
+    if let Some(path) = &opts.plan {
+        write_fixes(path, &fixes)?;
+    }
+    if !opts.apply {
+        return Ok(());
+    }
+
+    let sender = new_sender(&rpc, &args)?;
+    let update_authority = read_keypair_file(
+        opts.update_authority
+            .as_ref()
+            .ok_or("--apply needs --update-authority")?,
+    )?;
+    let journal = Journal::open(&db, opts.job.as_deref().unwrap_or("reconcile-cache"))?;
+    journal.resolve_sent(&sender)?;
+
+    for (fix, (mint, metadata)) in fixes.iter().zip(&found) {
+        if journal.is_done(&fix.mint)? {
+            continue;
+        }
+        journal.plan(&fix.mint, &format!("set uri {}", fix.uri))?;
+        let outcome = apply_fix(&sender, &journal, &update_authority, mint, metadata, fix);
+        eprintln!("{} {:?}", fix.mint, outcome);
+        journal.record(&fix.mint, &outcome)?;
+    }
+
+    journal.finish()
+}
+
+/// Metadata links of the named cache, by item name.
+fn read_cache_links(
+    db: &Connection,
+    cache: &str,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut stmt = db.prepare("SELECT name, link FROM cache_items WHERE cache = ?1")?;
+    let links = stmt
+        .query_map(params![cache], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    Ok(links)
+}
@@ -0,0 +1,35 @@
+//! The candy machine cli's `cache.json`, which records what was uploaded
+//! for each item of the collection.
+
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fs::File, io::BufReader};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CacheFile {
+    pub items: HashMap<String, CacheItem>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CacheItem {
+    pub link: String,
+    #[serde(rename = "imageLink")]
+    pub image_link: Option<String>,
+    pub name: String,
+    #[serde(rename = "onChain")]
+    pub on_chain: bool,
+}
+
+impl CacheFile {
+    pub fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Items keyed by name, e.g. `ExiledApe 1/518`.
+    pub fn by_name(&self) -> HashMap<String, CacheItem> {
+        self.items
+            .values()
+            .map(|item| (item.name.clone(), item.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,162 @@
+//! Checks what a custody wallet holds against what it is supposed to.
+//!
+//! The expected set is kept per custody wallet in `custody_expected`,
+//! imported from a mint list like `custodied.log`. Everything the wallet
+//! holds is found from the token program's accounts rather than from the
+//! mints we know about, so tokens that aren't apes show up too.
+
+use crate::{
+    audit::{print_findings, Finding},
+    CustodyReport,
+};
+use rusqlite::params;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+};
+use solana_sdk::{account::ReadableAccount, program_pack::Pack, pubkey::Pubkey};
+use spl_token::state::Account;
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+use xapes_core::schema;
+
+/// Offset of the owner in a token account, after the mint.
+const TOKEN_OWNER_OFFSET: usize = 32;
+
+pub async fn custody_report(opts: CustodyReport) -> Result<(), Box<dyn Error>> {
+    let mut db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+    let custody: Pubkey = opts.custody.parse()?;
+
+    if let Some(path) = &opts.import {
+        let mut mints = vec![];
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let mint = line.trim();
+            if !mint.is_empty() {
+                mints.push(mint.parse::<Pubkey>()?);
+            }
+        }
+        let tx = db.transaction()?;
+        tx.execute(
+            "DELETE FROM custody_expected WHERE custody = ?1",
+            params![opts.custody],
+        )?;
+        for mint in &mints {
+            tx.execute(
+                "INSERT OR IGNORE INTO custody_expected
+                (custody, mint_address) values
+                (     ?1,           ?2)",
+                params![opts.custody, mint.to_string()],
+            )?;
+        }
+        tx.commit()?;
+        eprintln!("expecting {} mints in {}", mints.len(), custody);
+    }
+
+    let mut stmt = db.prepare("SELECT mint_address FROM custody_expected WHERE custody = ?1")?;
+    let expected = stmt
+        .query_map(params![opts.custody], |row| row.get::<_, String>(0))?
+        .collect::<Result<BTreeSet<_>, _>>()?;
+
+    let mut stmt = db.prepare(
+        "SELECT mint_address, 'mirc' FROM mirc_mints
+         UNION ALL
+         SELECT mint_address, 'mono' FROM mono_mints",
+    )?;
+    let collections = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    let mut stmt = db.prepare("SELECT mirc_mint_address, mono_mint_address FROM entanglements")?;
+    let mut partners = HashMap::new();
+    for row in stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })? {
+        let (mirc, mono) = row?;
+        partners.insert(mirc.clone(), ("mono", mono.clone()));
+        partners.insert(mono, ("mirc", mirc));
+    }
+
+    let held: BTreeSet<String> = token_accounts_of(&rpc, &custody)?
+        .into_iter()
+        .filter(|account| account.amount > 0)
+        .map(|account| account.mint.to_string())
+        .collect();
+    eprintln!(
+        "{} holds {} mints, {} expected",
+        custody,
+        held.len(),
+        expected.len()
+    );
+
+    let finding = |mint: &String, check: &str| {
+        let collection = collections
+            .get(mint)
+            .cloned()
+            .unwrap_or_else(|| "unknown".to_string());
+        let detail = match partners.get(mint) {
+            Some((side, partner)) => {
+                format!("{} side, entangled with {} {}", collection, side, partner)
+            }
+            None => "not entangled".to_string(),
+        };
+        Finding {
+            collection,
+            mint: mint.clone(),
+            check: check.to_string(),
+            detail,
+        }
+    };
+
+    let mut findings = vec![];
+    for mint in expected.difference(&held) {
+        findings.push(finding(mint, "missing"));
+    }
+    for mint in held.difference(&expected) {
+        findings.push(finding(mint, "unexpected"));
+    }
+    if opts.all {
+        for mint in held.intersection(&expected) {
+            findings.push(finding(mint, "held"));
+        }
+    }
+
+    print_findings(&findings);
+    if let Some(path) = &opts.json {
+        serde_json::to_writer_pretty(File::create(path)?, &findings)?;
+    }
+    Ok(())
+}
+
+/// Every token account `owner` has.
+fn token_accounts_of(rpc: &RpcClient, owner: &Pubkey) -> Result<Vec<Account>, Box<dyn Error>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::DataSize(Account::LEN as u64),
+            RpcFilterType::Memcmp(Memcmp {
+                offset: TOKEN_OWNER_OFFSET,
+                bytes: MemcmpEncodedBytes::Base58(owner.to_string()),
+                encoding: None,
+            }),
+        ]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    rpc.get_program_accounts_with_config(&spl_token::id(), config)?
+        .into_iter()
+        .map(|(_, account)| Ok(Account::unpack(account.data())?))
+        .collect()
+}
@@ -0,0 +1,75 @@
+//! Finding a collection's mints from the metadata program's accounts
+//! rather than from a hand-maintained list.
+
+use mpl_token_metadata::state::{MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+};
+use solana_sdk::{account::ReadableAccount, pubkey::Pubkey};
+use std::error::Error;
+use xapes_core::metadata::decode_metadata;
+
+/// Offset of the update authority in a metadata account, after the key.
+const UPDATE_AUTHORITY_OFFSET: usize = 1;
+
+/// Offset of the first creator's address: key, update authority, mint,
+/// the three length-prefixed padded strings, seller fee, the creators
+/// option flag and the creators vec length.
+const FIRST_CREATOR_OFFSET: usize =
+    1 + 32 + 32 + 4 + MAX_NAME_LENGTH + 4 + MAX_SYMBOL_LENGTH + 4 + MAX_URI_LENGTH + 2 + 1 + 4;
+
+pub enum MintFilter {
+    /// Candy machine v1 mints list the candy machine as first creator.
+    FirstCreator(Pubkey),
+    UpdateAuthority(Pubkey),
+}
+
+/// Returns every mint whose metadata matches `filter` and whose name starts
+/// with `name_prefix`, sorted by address.
+pub fn discover_mints(
+    rpc: &RpcClient,
+    filter: MintFilter,
+    name_prefix: Option<&str>,
+) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+    let (offset, address) = match filter {
+        MintFilter::FirstCreator(address) => (FIRST_CREATOR_OFFSET, address),
+        MintFilter::UpdateAuthority(address) => (UPDATE_AUTHORITY_OFFSET, address),
+    };
+
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp {
+            offset,
+            bytes: MemcmpEncodedBytes::Base58(address.to_string()),
+            encoding: None,
+        })]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    let accounts = rpc.get_program_accounts_with_config(&mpl_token_metadata::id(), config)?;
+
+    let mut mints = vec![];
+    for (address, account) in accounts {
+        let metadata = match decode_metadata(account.data()) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!("skipping {}: {}", address, e);
+                continue;
+            }
+        };
+        if let Some(prefix) = name_prefix {
+            if !metadata.data.name.starts_with(prefix) {
+                continue;
+            }
+        }
+        mints.push(metadata.mint);
+    }
+    mints.sort();
+    Ok(mints)
+}
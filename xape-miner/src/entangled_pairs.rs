@@ -0,0 +1,175 @@
+//! The entangled pair accounts that actually exist on chain, as opposed to
+//! the `entanglements` plan.
+//!
+//! A pair is linked to the plan row of its mirc ape when it entangles that
+//! ape with the mono ape the plan pairs it with, mono as mint a and mirc as
+//! mint b. Pairs the plan doesn't account for, and plan rows no pair was
+//! created for, are reported.
+
+use crate::{
+    audit::{print_findings, Finding},
+    ImportEntangledPairs,
+};
+use rusqlite::params;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+};
+use solana_sdk::{account::ReadableAccount, pubkey::Pubkey};
+use std::{collections::HashMap, error::Error};
+use xapes_core::{
+    entangler::{self, decode_entangled_pair, EntangledPair, ENTANGLED_PAIR_AUTHORITY_OFFSET},
+    schema,
+};
+
+pub async fn import_entangled_pairs(opts: ImportEntangledPairs) -> Result<(), Box<dyn Error>> {
+    let mut db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+    let authority: Pubkey = opts.authority.parse()?;
+
+    let pairs = fetch_entangled_pairs(&rpc, &authority)?;
+    eprintln!("found {} entangled pairs under {}", pairs.len(), authority);
+
+    let mut stmt = db.prepare("SELECT mirc_mint_address, mono_mint_address FROM entanglements")?;
+    let plan = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    drop(stmt);
+    let planned_mirc_of: HashMap<&String, &String> =
+        plan.iter().map(|(mirc, mono)| (mono, mirc)).collect();
+
+    let mut findings = vec![];
+    let mut linked = HashMap::new();
+    let tx = db.transaction()?;
+    tx.execute(
+        "DELETE FROM entangled_pairs WHERE authority = ?1",
+        params![opts.authority],
+    )?;
+    for (address, pair) in &pairs {
+        let mint_a = pair.mint_a.to_string();
+        let mint_b = pair.mint_b.to_string();
+        let mirc_mint_address = match plan.get(&mint_b) {
+            Some(mono) if *mono == mint_a => Some(mint_b.clone()),
+            _ => None,
+        };
+
+        match &mirc_mint_address {
+            Some(mirc) => {
+                linked.insert(mirc.clone(), address);
+            }
+            None => findings.push(Finding {
+                collection: "-".to_string(),
+                mint: mint_b.clone(),
+                check: "unplanned".to_string(),
+                detail: format!(
+                    "pair {} entangles {} with {}, the plan pairs {}",
+                    address,
+                    mint_a,
+                    mint_b,
+                    describe_plan(&plan, &planned_mirc_of, &mint_a, &mint_b)
+                ),
+            }),
+        }
+
+        tx.execute(
+            "INSERT INTO entangled_pairs
+            (address, authority, treasury_mint, mint_a, mint_b, token_a_escrow, token_b_escrow, price, paid, pays_every_time, mirc_mint_address, imported_at) values
+            (     ?1,        ?2,            ?3,     ?4,     ?5,             ?6,             ?7,    ?8,   ?9,             ?10,               ?11, datetime('now'))",
+            params![
+                address.to_string(),
+                pair.authority.to_string(),
+                pair.treasury_mint.to_string(),
+                mint_a,
+                mint_b,
+                pair.token_a_escrow.to_string(),
+                pair.token_b_escrow.to_string(),
+                pair.price as i64,
+                pair.paid,
+                pair.pays_every_time,
+                mirc_mint_address
+            ],
+        )?;
+    }
+    tx.commit()?;
+
+    let mut unentangled: Vec<_> = plan
+        .iter()
+        .filter(|(mirc, _)| !linked.contains_key(*mirc))
+        .collect();
+    unentangled.sort();
+    for (mirc, mono) in unentangled {
+        findings.push(Finding {
+            collection: "mirc".to_string(),
+            mint: mirc.clone(),
+            check: "unentangled".to_string(),
+            detail: format!("planned with mono {} but no pair exists", mono),
+        });
+    }
+
+    print_findings(&findings);
+    eprintln!(
+        "{} of {} planned entanglements exist on chain",
+        linked.len(),
+        plan.len()
+    );
+    Ok(())
+}
+
+/// What the plan says about the mints of a pair it doesn't match.
+fn describe_plan(
+    plan: &HashMap<String, String>,
+    planned_mirc_of: &HashMap<&String, &String>,
+    mint_a: &String,
+    mint_b: &String,
+) -> String {
+    let mut parts = vec![];
+    if let Some(mono) = plan.get(mint_b) {
+        parts.push(format!("mirc {} with mono {}", mint_b, mono));
+    }
+    if let Some(mirc) = planned_mirc_of.get(mint_a) {
+        parts.push(format!("mono {} with mirc {}", mint_a, mirc));
+    }
+    if let Some(mono) = plan.get(mint_a) {
+        parts.push(format!("mirc {} with mono {}", mint_a, mono));
+    }
+    if parts.is_empty() {
+        "neither".to_string()
+    } else {
+        parts.join(" and ")
+    }
+}
+
+/// Every entangled pair account `authority` controls, with its address.
+fn fetch_entangled_pairs(
+    rpc: &RpcClient,
+    authority: &Pubkey,
+) -> Result<Vec<(Pubkey, EntangledPair)>, Box<dyn Error>> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp {
+            offset: ENTANGLED_PAIR_AUTHORITY_OFFSET,
+            bytes: MemcmpEncodedBytes::Base58(authority.to_string()),
+            encoding: None,
+        })]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    let accounts = rpc.get_program_accounts_with_config(&entangler::program_id(), config)?;
+
+    let mut pairs = vec![];
+    for (address, account) in accounts {
+        match decode_entangled_pair(account.data()) {
+            Ok(pair) => pairs.push((address, pair)),
+            Err(e) => eprintln!("skipping {}: {}", address, e),
+        }
+    }
+    pairs.sort_by_key(|(address, _)| *address);
+    Ok(pairs)
+}
@@ -0,0 +1,92 @@
+//! Uri and name corrections for individual mints, written out as a plan
+//! csv (`mint,uri,name`) or applied directly.
+
+use mpl_token_metadata::{
+    instruction::update_metadata_accounts,
+    state::{Data, Metadata},
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use std::{error::Error, fs::File};
+use xapes_core::{
+    journal::{Journal, Outcome},
+    metadata::print_data_diff,
+    pda::find_metadata_address,
+    sender::Sender,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UriFix {
+    pub mint: String,
+    pub uri: String,
+    /// Left as it is on chain when empty.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+pub fn read_fixes(path: &str) -> Result<Vec<UriFix>, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_reader(File::open(path)?);
+    let mut fixes = vec![];
+    for result in rdr.deserialize() {
+        fixes.push(result?);
+    }
+    Ok(fixes)
+}
+
+pub fn write_fixes(path: &str, fixes: &[UriFix]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(File::create(path)?);
+    for fix in fixes {
+        wtr.serialize(fix)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Sets the uri, and name if the fix has one, of `mint` whose current
+/// metadata is `metadata`, unless they already match.
+pub fn apply_fix(
+    sender: &Sender,
+    journal: &Journal,
+    update_authority: &Keypair,
+    mint: &Pubkey,
+    metadata: &Metadata,
+    fix: &UriFix,
+) -> Result<Outcome, Box<dyn Error>> {
+    let data = Data {
+        uri: fix.uri.clone(),
+        name: fix
+            .name
+            .clone()
+            .unwrap_or_else(|| metadata.data.name.clone()),
+        ..metadata.data.clone()
+    };
+    if data == metadata.data {
+        return Ok(Outcome::Unchanged);
+    }
+    if metadata.update_authority != update_authority.pubkey() {
+        return Err(format!(
+            "{} is updated by {}, not {}",
+            mint,
+            metadata.update_authority,
+            update_authority.pubkey()
+        )
+        .into());
+    }
+    print_data_diff(mint, &metadata.data, &data);
+
+    let instruction = update_metadata_accounts(
+        mpl_token_metadata::id(),
+        find_metadata_address(*mint),
+        metadata.update_authority,
+        None,
+        Some(data),
+        None,
+    );
+    let sig = sender.send_tracked(&[instruction], &[update_authority], |sig, blockhash| {
+        journal.sent(&fix.mint, sig, blockhash)
+    })?;
+    Ok(Outcome::from(sig))
+}
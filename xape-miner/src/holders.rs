@@ -0,0 +1,380 @@
+//! Snapshots of who holds every mirc and mono ape.
+//!
+//! Each run is stored as one row of `holder_snapshots` with a row of
+//! `holders` per mint, so later runs never overwrite earlier ones and any
+//! two can be compared. A mint no account holds, as when it has been
+//! burned, is kept with an empty owner.
+//!
+//! `diff_snapshots` compares two of them. Besides each mint that changed
+//! hands and the wallets that came and went, it finds entanglement flips:
+//! a wallet that held one side of an entangled pair and now holds the
+//! other side instead, as after swapping through the entangler.
+
+use crate::{rate_limit::RateLimiter, DiffSnapshots, SnapshotHolders};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs::File,
+    io::{self, Write},
+};
+use xapes_core::{holders::find_holder, schema};
+
+pub async fn snapshot_holders(opts: SnapshotHolders) -> Result<(), Box<dyn Error>> {
+    let mut db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+    let limiter = RateLimiter::new(opts.rps);
+
+    let collections: &[&str] = match opts.collection.as_deref() {
+        Some("mirc") => &["mirc"],
+        Some("mono") => &["mono"],
+        Some(other) => return Err(format!("unknown collection {:?}", other).into()),
+        None => &["mirc", "mono"],
+    };
+
+    let mut mints = vec![];
+    for &collection in collections {
+        let mut stmt = db.prepare(&format!(
+            "SELECT mint_address FROM {}_mints ORDER BY mint_address",
+            collection
+        ))?;
+        for mint in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            mints.push((collection, mint?.parse::<Pubkey>()?));
+        }
+    }
+    eprintln!("snapshotting holders of {} mints", mints.len());
+
+    let mut holders = Vec::with_capacity(mints.len());
+    for (i, (collection, mint)) in mints.iter().enumerate() {
+        // find_holder makes two requests
+        limiter.wait().await;
+        limiter.wait().await;
+        holders.push((*collection, mint, find_holder(&rpc, mint)?));
+        if (i + 1) % 100 == 0 {
+            eprintln!("  {}/{}", i + 1, mints.len());
+        }
+    }
+
+    let tx = db.transaction()?;
+    tx.execute(
+        "INSERT INTO holder_snapshots
+        (name, taken_at) values
+        (  ?1, datetime('now'))",
+        params![opts.name],
+    )?;
+    let snapshot_id = tx.last_insert_rowid();
+    for (collection, mint, holder) in &holders {
+        tx.execute(
+            "INSERT INTO holders
+            (snapshot_id, mint_address, collection, token_account, owner, delegate, frozen, slot) values
+            (         ?1,           ?2,         ?3,            ?4,    ?5,       ?6,     ?7,   ?8)",
+            params![
+                snapshot_id,
+                mint.to_string(),
+                collection,
+                holder.as_ref().map(|h| h.token_account.to_string()),
+                holder.as_ref().map(|h| h.owner.to_string()),
+                holder
+                    .as_ref()
+                    .and_then(|h| h.delegate)
+                    .map(|d| d.to_string()),
+                holder.as_ref().map(|h| h.frozen),
+                holder.as_ref().map(|h| h.slot as i64),
+            ],
+        )?;
+    }
+    tx.commit()?;
+
+    if let Some(path) = &opts.csv {
+        // the wallet, mint shape of refunded-holders.csv
+        let mut file = File::create(path)?;
+        for (_, mint, holder) in &holders {
+            if let Some(holder) = holder {
+                writeln!(file, "{}, {}", holder.owner, mint)?;
+            }
+        }
+    }
+
+    let unheld = holders.iter().filter(|(_, _, h)| h.is_none()).count();
+    let frozen = holders
+        .iter()
+        .filter(|(_, _, h)| h.as_ref().map_or(false, |h| h.frozen))
+        .count();
+    let delegated = holders
+        .iter()
+        .filter(|(_, _, h)| h.as_ref().map_or(false, |h| h.delegate.is_some()))
+        .count();
+    eprintln!(
+        "snapshot {}: {} mints, {} held by no one, {} frozen, {} delegated",
+        snapshot_id,
+        holders.len(),
+        unheld,
+        frozen,
+        delegated
+    );
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Change {
+    kind: &'static str,
+    mint: Option<String>,
+    collection: Option<String>,
+    from_owner: Option<String>,
+    to_owner: Option<String>,
+    detail: Option<String>,
+}
+
+struct HeldMint {
+    collection: String,
+    owner: Option<String>,
+}
+
+pub async fn diff_snapshots(opts: DiffSnapshots) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+
+    let to = match &opts.to {
+        Some(to) => find_snapshot(&db, to)?,
+        None => db
+            .query_row("SELECT max(id) FROM holder_snapshots", [], |row| {
+                row.get::<_, Option<i64>>(0)
+            })?
+            .ok_or("there are no snapshots yet")?,
+    };
+    let from = match &opts.from {
+        Some(from) => find_snapshot(&db, from)?,
+        None => db
+            .query_row(
+                "SELECT max(id) FROM holder_snapshots WHERE id < ?1",
+                params![to],
+                |row| row.get::<_, Option<i64>>(0),
+            )?
+            .ok_or_else(|| format!("there is no snapshot before {}", to))?,
+    };
+    eprintln!("comparing snapshot {} with snapshot {}", from, to);
+
+    let before = read_snapshot(&db, from)?;
+    let after = read_snapshot(&db, to)?;
+
+    let mut changes = transfers(&before, &after);
+
+    let wallets_before = wallet_counts(&before);
+    let wallets_after = wallet_counts(&after);
+    for (wallet, count) in &wallets_after {
+        if !wallets_before.contains_key(wallet) {
+            changes.push(Change {
+                kind: "new_wallet",
+                mint: None,
+                collection: None,
+                from_owner: None,
+                to_owner: Some(wallet.to_string()),
+                detail: Some(format!("holds {}", count)),
+            });
+        }
+    }
+    for (wallet, count) in &wallets_before {
+        if !wallets_after.contains_key(wallet) {
+            changes.push(Change {
+                kind: "exited_wallet",
+                mint: None,
+                collection: None,
+                from_owner: Some(wallet.to_string()),
+                to_owner: None,
+                detail: Some(format!("held {}", count)),
+            });
+        }
+    }
+
+    let mut stmt = db.prepare("SELECT mirc_mint_address, mono_mint_address FROM entanglements")?;
+    let pairs = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (mirc, mono) in &pairs {
+        let owner = |snapshot: &BTreeMap<String, HeldMint>, mint: &String| {
+            snapshot.get(mint).and_then(|h| h.owner.clone())
+        };
+        let sides = [(mirc, mono, "mirc", "mono"), (mono, mirc, "mono", "mirc")];
+        for (held, swapped, held_side, swapped_side) in sides {
+            let wallet = match owner(&before, held) {
+                Some(wallet) => wallet,
+                None => continue,
+            };
+            if owner(&after, swapped).as_ref() == Some(&wallet)
+                && owner(&before, swapped).as_ref() != Some(&wallet)
+                && owner(&after, held).as_ref() != Some(&wallet)
+            {
+                changes.push(Change {
+                    kind: "flip",
+                    mint: Some(swapped.clone()),
+                    collection: Some(swapped_side.to_string()),
+                    from_owner: Some(wallet.clone()),
+                    to_owner: Some(wallet),
+                    detail: Some(format!("{} {} -> {}", held_side, held, swapped_side)),
+                });
+            }
+        }
+    }
+
+    let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+    for change in &changes {
+        *kinds.entry(change.kind).or_default() += 1;
+    }
+    for (kind, count) in &kinds {
+        eprintln!("{:<14} {:>5}", kind, count);
+    }
+
+    let out: Box<dyn Write> = match &opts.out {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    match opts.format.as_str() {
+        "csv" => {
+            let mut wtr = csv::Writer::from_writer(out);
+            for change in &changes {
+                wtr.serialize(change)?;
+            }
+            wtr.flush()?;
+        }
+        "json" => serde_json::to_writer_pretty(out, &changes)?,
+        other => return Err(format!("unknown format {:?}, want csv or json", other).into()),
+    }
+    Ok(())
+}
+
+/// The id of the snapshot with id or name `snapshot`.
+fn find_snapshot(db: &Connection, snapshot: &str) -> Result<i64, Box<dyn Error>> {
+    let id = db
+        .query_row(
+            "SELECT id FROM holder_snapshots WHERE name = ?1 OR CAST(id AS text) = ?1",
+            params![snapshot],
+            |row| row.get(0),
+        )
+        .optional()?;
+    id.ok_or_else(|| format!("no snapshot {:?}", snapshot).into())
+}
+
+fn read_snapshot(db: &Connection, id: i64) -> Result<BTreeMap<String, HeldMint>, Box<dyn Error>> {
+    let mut stmt =
+        db.prepare("SELECT mint_address, collection, owner FROM holders WHERE snapshot_id = ?1")?;
+    let rows = stmt.query_map(params![id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            HeldMint {
+                collection: row.get(1)?,
+                owner: row.get(2)?,
+            },
+        ))
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Every mint whose owner differs between `before` and `after`. A mint only
+/// one snapshot has, as when the other only covered one collection, is
+/// compared against no owner at all.
+fn transfers(
+    before: &BTreeMap<String, HeldMint>,
+    after: &BTreeMap<String, HeldMint>,
+) -> Vec<Change> {
+    let mut changes = vec![];
+    let mints: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    for mint in mints {
+        let held_before = before.get(mint);
+        let held_after = after.get(mint);
+        let owner_before = held_before.and_then(|h| h.owner.clone());
+        let owner_after = held_after.and_then(|h| h.owner.clone());
+        if owner_before != owner_after {
+            let collection = held_after.or(held_before).map(|h| h.collection.clone());
+            changes.push(Change {
+                kind: "transfer",
+                mint: Some(mint.clone()),
+                collection,
+                from_owner: owner_before,
+                to_owner: owner_after,
+                detail: None,
+            });
+        }
+    }
+    changes
+}
+
+/// How many mints each wallet holds.
+fn wallet_counts(snapshot: &BTreeMap<String, HeldMint>) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for held in snapshot.values() {
+        if let Some(owner) = &held.owner {
+            *counts.entry(owner.as_str()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(held: &[(&str, &str, Option<&str>)]) -> BTreeMap<String, HeldMint> {
+        held.iter()
+            .map(|(mint, collection, owner)| {
+                (
+                    mint.to_string(),
+                    HeldMint {
+                        collection: collection.to_string(),
+                        owner: owner.map(str::to_string),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn summary(changes: &[Change]) -> Vec<(&str, &str, Option<&str>, Option<&str>)> {
+        changes
+            .iter()
+            .map(|c| {
+                (
+                    c.mint.as_deref().unwrap(),
+                    c.collection.as_deref().unwrap(),
+                    c.from_owner.as_deref(),
+                    c.to_owner.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_mints_either_snapshot_lacks() {
+        let before = snapshot(&[
+            ("kept", "mirc", Some("alice")),
+            ("sold", "mirc", Some("alice")),
+            ("gone", "mono", Some("bob")),
+        ]);
+        let after = snapshot(&[
+            ("kept", "mirc", Some("alice")),
+            ("sold", "mirc", Some("carol")),
+            ("new", "mono", Some("dave")),
+        ]);
+        assert_eq!(
+            summary(&transfers(&before, &after)),
+            [
+                ("gone", "mono", Some("bob"), None),
+                ("new", "mono", None, Some("dave")),
+                ("sold", "mirc", Some("alice"), Some("carol")),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_burned_mint_loses_its_owner() {
+        let before = snapshot(&[("burned", "mono", Some("bob"))]);
+        let after = snapshot(&[("burned", "mono", None)]);
+        assert_eq!(
+            summary(&transfers(&before, &after)),
+            [("burned", "mono", Some("bob"), None)]
+        );
+        assert!(transfers(&after, &snapshot(&[])).is_empty());
+    }
+}
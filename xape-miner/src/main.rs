@@ -1,45 +1,207 @@
-use borsh::de::BorshDeserialize;
+mod asset_cache;
+mod audit;
+mod cache;
+mod candy_machine;
+mod custody;
+mod discover;
+mod entangled_pairs;
+mod fixes;
+mod holders;
+mod rarity;
+mod rate_limit;
+mod royalties;
+mod validate;
+
+use asset_cache::AssetCache;
+use audit::{print_findings, Finding};
+use borsh::BorshSerialize;
+use discover::MintFilter;
+use fixes::{apply_fix, read_fixes};
+use futures::{stream, StreamExt};
 use gumdrop::Options;
-use metaplex_token_metadata::{
-    instruction::update_metadata_accounts,
-    state::{Data, Metadata},
-};
+use mpl_token_metadata::state::Metadata;
+use rate_limit::RateLimiter;
 use rusqlite::{params, Connection};
-use serde::Deserialize;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::{
-    account::ReadableAccount, pubkey::Pubkey, signature::read_keypair_file, signer::Signer,
-    transaction::Transaction,
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use solana_client::{rpc_client::RpcClient, rpc_request::MAX_MULTIPLE_ACCOUNTS};
+use solana_sdk::{pubkey::Pubkey, signature::read_keypair_file};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Debug,
+    fs::File,
+    io::BufRead,
+    io::BufReader,
 };
-use std::{error::Error, fmt::Debug, fs::File, io::BufRead, io::BufReader};
 use tokio::join;
+use xapes_core::{
+    journal::Journal,
+    metadata::{decode_metadata, fetch_metadata_batch},
+    pda::find_metadata_address,
+    schema,
+    sender::Sender,
+};
 
 #[derive(Clone, Debug, Options)]
 struct Args {
+    #[options(help = "simulate transactions instead of sending them")]
+    dry_run: bool,
+    #[options(help = "commitment to confirm transactions to", default = "confirmed")]
+    commitment: String,
+    #[options(
+        help = "times to re-sign and resend an expired transaction",
+        default = "5"
+    )]
+    retries: u32,
     #[options(command)]
     command: Option<Command>,
 }
 
 #[derive(Clone, Debug, Options)]
 enum Command {
-    #[options(help = "fix some busted ghosts")]
-    FixGhosts(FixGhosts),
+    #[options(help = "report mints that have drifted from the expected collection state")]
+    Audit(Audit),
+    #[options(help = "find a collection's mints on chain and load them into sqlite")]
+    DiscoverMints(DiscoverMints),
+    #[options(help = "compare what a custody wallet holds with what it should")]
+    CustodyReport(CustodyReport),
+    #[options(help = "compare two holder snapshots")]
+    DiffSnapshots(DiffSnapshots),
+    #[options(help = "set the uris, and optionally names, listed in a plan csv")]
+    FixUris(FixUris),
+    #[options(help = "load a candy machine cache.json into sqlite")]
+    ImportCache(ImportCache),
+    #[options(help = "load the entangled pair accounts on chain into sqlite")]
+    ImportEntangledPairs(ImportEntangledPairs),
     #[options(help = "load the mint files into sqlite")]
     LoadBlanks(LoadBlanks),
     #[options(help = "load the mint files into sqlite")]
     LoadMints(LoadMints),
     #[options(help = "populate entanglements table from mints")]
     PlanEntanglements(PlanEntanglements),
+    #[options(help = "rank mints by trait rarity")]
+    Rarity(Rarity),
+    #[options(help = "compare mirc names and uris with the candy machine cache and fix them")]
+    ReconcileCache(ReconcileCache),
+    #[options(help = "record royalty payments to the creators from their transaction history")]
+    ScanRoyalties(ScanRoyalties),
+    #[options(help = "record who holds every mint, as a new dated snapshot")]
+    SnapshotHolders(SnapshotHolders),
+    #[options(help = "check each mint's off-chain json against the metaplex standard")]
+    ValidateJson(ValidateJson),
+}
+
+#[derive(Clone, Debug, Options)]
+struct Audit {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "candy machine cache.json to check mirc uris against")]
+    cache: Option<String>,
+    #[options(help = "royalty policy toml to check creators against")]
+    policy: Option<String>,
+    #[options(help = "expect metadata to be immutable")]
+    immutable: bool,
+    #[options(help = "also write findings to this json file")]
+    json: Option<String>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct DiscoverMints {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "mirc or mono")]
+    collection: String,
+    #[options(help = "first creator address")]
+    first_creator: Option<String>,
+    #[options(help = "candy machine id, the first creator of candy machine v1 mints")]
+    candy_machine: Option<String>,
+    #[options(help = "update authority address")]
+    update_authority: Option<String>,
+    #[options(help = "only keep mints whose name starts with this")]
+    name_prefix: Option<String>,
+    #[options(
+        help = "requests per second cap, rpc and json combined",
+        default = "10"
+    )]
+    rps: u32,
+    #[options(help = "off-chain json fetched at once", default = "8")]
+    concurrency: usize,
+    #[options(help = "asset cache directory", default = "../data/asset-cache")]
+    cache_dir: String,
+    #[options(help = "revalidate cached json and fetch metadata accounts again")]
+    refresh: bool,
+    #[options(help = "also fetch every image into the asset cache")]
+    mirror_images: bool,
+}
+
+#[derive(Clone, Debug, Options)]
+struct CustodyReport {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "custody wallet")]
+    custody: String,
+    #[options(help = "replace the expected mints with this mint list, like custodied.log")]
+    import: Option<String>,
+    #[options(help = "also list the mints held as expected")]
+    all: bool,
+    #[options(help = "also write findings to this json file")]
+    json: Option<String>,
 }
 
 #[derive(Clone, Debug, Options)]
-struct FixGhosts {
+struct DiffSnapshots {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "snapshot id or name, defaults to the one before --to")]
+    from: Option<String>,
+    #[options(help = "snapshot id or name, defaults to the latest")]
+    to: Option<String>,
+    #[options(help = "csv or json", default = "csv")]
+    format: String,
+    #[options(help = "write the changes here instead of stdout")]
+    out: Option<String>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct FixUris {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "journal job name, defaults to fix-uris")]
+    job: Option<String>,
     #[options(help = "rpc server")]
     rpc: String,
+    #[options(help = "csv of mint,uri and optionally name")]
+    plan: String,
     #[options(help = "update authority")]
     update_authority: String,
 }
 
+#[derive(Clone, Debug, Options)]
+struct ImportCache {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "candy machine cache.json")]
+    cache: String,
+    #[options(help = "name to import the cache as, defaults to its directory name")]
+    name: Option<String>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct ImportEntangledPairs {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "entanglement authority the pairs were created under")]
+    authority: String,
+}
+
 #[derive(Clone, Debug, Options)]
 struct LoadBlanks {
     #[options(help = "blanks csv file")]
@@ -56,25 +218,127 @@ struct LoadMints {
     mirc_file: String,
     #[options(help = "mono mints file")]
     mono_file: String,
+    #[options(help = "build the tables from the asset cache alone")]
+    offline: bool,
     #[options(help = "rpc server")]
     rpc: String,
+    #[options(
+        help = "requests per second cap, rpc and json combined",
+        default = "10"
+    )]
+    rps: u32,
+    #[options(help = "off-chain json fetched at once", default = "8")]
+    concurrency: usize,
+    #[options(help = "asset cache directory", default = "../data/asset-cache")]
+    cache_dir: String,
+    #[options(help = "revalidate cached json and fetch metadata accounts again")]
+    refresh: bool,
+    #[options(help = "also fetch every image into the asset cache")]
+    mirror_images: bool,
 }
 
 #[derive(Clone, Debug, Options)]
 struct PlanEntanglements {
     #[options(help = "sqlite db path")]
     db: String,
+    #[options(help = "write the plan even if some mints can't be paired")]
+    allow_partial: bool,
+    #[options(help = "keep pairs from earlier plans that this one leaves out")]
+    keep_unplanned: bool,
+}
+
+#[derive(Clone, Debug, Options)]
+struct Rarity {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "mirc or mono, defaults to both")]
+    collection: Option<String>,
+    #[options(help = "how many of the rarest to print", default = "20")]
+    top: usize,
+}
+
+#[derive(Clone, Debug, Options)]
+struct ReconcileCache {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "imported cache to compare against", default = "mainnet-beta")]
+    cache: String,
+    #[options(help = "write the fixes to this csv")]
+    plan: Option<String>,
+    #[options(help = "send the fixes")]
+    apply: bool,
+    #[options(help = "update authority keypair, needed with --apply")]
+    update_authority: Option<String>,
+    #[options(help = "journal job name, defaults to reconcile-cache")]
+    job: Option<String>,
+}
+
+#[derive(Clone, Debug, Options)]
+struct ScanRoyalties {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(
+        help = "royalty policy toml naming the creators",
+        default = "../policy.toml"
+    )]
+    policy: String,
+    #[options(help = "scan this address instead of the policy creators")]
+    address: Vec<String>,
+    #[options(
+        help = "summarize income since this date, defaults to the last secondary distribution"
+    )]
+    since: Option<String>,
+    #[options(help = "requests per second cap", default = "10")]
+    rps: u32,
+}
+
+#[derive(Clone, Debug, Options)]
+struct SnapshotHolders {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "name for the snapshot")]
+    name: Option<String>,
+    #[options(help = "mirc or mono, defaults to both")]
+    collection: Option<String>,
+    #[options(help = "also write wallet, mint rows to this csv")]
+    csv: Option<String>,
+    #[options(help = "requests per second cap", default = "10")]
+    rps: u32,
+}
+
+#[derive(Clone, Debug, Options)]
+struct ValidateJson {
+    #[options(help = "sqlite db path")]
+    db: String,
+    #[options(help = "rpc server")]
+    rpc: String,
+    #[options(help = "mirc or mono, defaults to both")]
+    collection: Option<String>,
+    #[options(
+        help = "requests per second cap, rpc and json combined",
+        default = "10"
+    )]
+    rps: u32,
+    #[options(help = "mints checked at once", default = "8")]
+    concurrency: usize,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 struct MircMeta {
     name: String,
     image: String,
-    attributes: Vec<MircAttr>,
+    attributes: Vec<Attribute>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
-struct MircAttr {
+struct Attribute {
+    #[serde(deserialize_with = "string_or_number")]
     value: String,
     trait_type: String,
 }
@@ -83,6 +347,19 @@ struct MircAttr {
 struct MonoMeta {
     name: String,
     image: String,
+    #[serde(default)]
+    attributes: Vec<Attribute>,
+}
+
+/// Some attribute values are json numbers; they are stored as text.
+fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(value) => Ok(value),
+        value => Ok(value.to_string()),
+    }
 }
 
 #[tokio::main]
@@ -91,101 +368,99 @@ async fn main() -> Result<(), Box<dyn Error>> {
     match args.clone().command {
         None => todo!(),
         Some(command) => match command {
-            Command::FixGhosts(opts) => fix_ghosts(opts).await,
+            Command::Audit(opts) => audit::audit(opts).await,
+            Command::DiscoverMints(opts) => discover_mints(opts).await,
+            Command::CustodyReport(opts) => custody::custody_report(opts).await,
+            Command::DiffSnapshots(opts) => holders::diff_snapshots(opts).await,
+            Command::FixUris(opts) => fix_uris(args, opts).await,
+            Command::ImportCache(opts) => cache::import_cache(opts).await,
+            Command::ImportEntangledPairs(opts) => {
+                entangled_pairs::import_entangled_pairs(opts).await
+            }
             Command::LoadBlanks(opts) => load_blanks(opts).await,
             Command::PlanEntanglements(opts) => plan_entanglements(opts).await,
+            Command::Rarity(opts) => rarity::rarity(opts).await,
+            Command::ReconcileCache(opts) => cache::reconcile_cache(args, opts).await,
             Command::LoadMints(opts) => load_mints(opts).await,
+            Command::ScanRoyalties(opts) => royalties::scan_royalties(opts).await,
+            Command::SnapshotHolders(opts) => holders::snapshot_holders(opts).await,
+            Command::ValidateJson(opts) => validate::validate_json(opts).await,
         },
     }
 }
 
-async fn fix_ghosts(opts: FixGhosts) -> Result<(), Box<dyn Error>> {
-    let rpc = RpcClient::new(opts.rpc);
-
-    let fixes = [
-        (
-            "C1zuSLjxYW3gSekUAMeSVt74dn826EUHoP1Pfjm8sh5Y",
-            "https://arweave.net/p7_PP3_b610qH7o0sM-n7twSpX94lF9TLwRHaSkKrYE",
-        ),
-        (
-            "4Q1TGHyQBnYDUKuFwQqYDkiCEDkh4FSrSpUp9rGEg7Kk",
-            "https://arweave.net/pMFdkuXKf9P6JrkHIeZuqNlcC-kIImc6y0NzZqRMj7s",
-        ),
-        (
-            "4GFfjpBYVoHcmnGbhqSXkS7CHHg3dWGSGV2G66PgRxkr",
-            "https://arweave.net/0erYimpankdX2yUCvkXEyoaxmCI1-dMAxIDtE-lhyjU",
-        ),
-        (
-            "7qtG5sLk7Z3uU4Bfy8hwuTt9mUWqZW3y9eASQsJDBbtV",
-            "https://arweave.net/1r_twlSBjrOZXq3lhEKYZvSiuKNugVcUNb1yIH5PaLU",
-        ),
-    ];
-
-    for (mint_address, meta_uri) in fixes {
-        let mint_address = mint_address.parse().unwrap();
-        let meta_address = find_metadata_address(mint_address);
-        let metadata = rpc.get_account(&meta_address)?;
-        let metadata = Metadata::deserialize(&mut metadata.data())?;
-
-        let update_authority = read_keypair_file(opts.update_authority.clone())?;
-
-        if {
-            let chain_uri = metadata.data.uri.trim_matches(char::from(0));
-            chain_uri != meta_uri
-        } {
-            eprintln!(" got {}\nwant {}", metadata.data.uri, meta_uri);
-
-            let (recent_blockhash, _) = rpc.get_recent_blockhash().unwrap();
-
-            let data = Data {
-                uri: meta_uri.to_string(),
-                ..metadata.data
-            };
-
-            let instruction = update_metadata_accounts(
-                metaplex_token_metadata::id(),
-                meta_address,
-                metadata.update_authority,
-                None,
-                Some(data),
-                None,
-            );
+async fn discover_mints(opts: DiscoverMints) -> Result<(), Box<dyn Error>> {
+    let rpc = RpcClient::new(opts.rpc.clone());
 
-            let instructions = &[instruction];
+    let filter = match (
+        opts.first_creator.as_ref().or(opts.candy_machine.as_ref()),
+        opts.update_authority.as_ref(),
+    ) {
+        (Some(creator), None) => MintFilter::FirstCreator(creator.parse()?),
+        (None, Some(authority)) => MintFilter::UpdateAuthority(authority.parse()?),
+        _ => return Err("give one creator or update authority filter".into()),
+    };
 
-            let signing_keypairs = &[&update_authority];
+    let mints = discover::discover_mints(&rpc, filter, opts.name_prefix.as_deref())?;
+    eprintln!("found {} {} mints", mints.len(), opts.collection);
 
-            let tx = Transaction::new_signed_with_payer(
-                instructions,
-                Some(&update_authority.pubkey()),
-                signing_keypairs,
-                recent_blockhash,
-            );
+    let limiter = RateLimiter::new(opts.rps);
+    let cache = AssetCache::open(&opts.cache_dir, false, opts.refresh)?;
+    let loader = MintLoader {
+        db: &opts.db,
+        rpc: &opts.rpc,
+        concurrency: opts.concurrency,
+        limiter: &limiter,
+        cache: &cache,
+        mirror_images: opts.mirror_images,
+    };
+    match opts.collection.as_str() {
+        "mirc" => load_mirc_mints(loader, &mints).await,
+        "mono" => load_mono_mints(loader, &mints).await,
+        other => Err(format!("unknown collection {:?}, want mirc or mono", other).into()),
+    }
+}
+
+async fn fix_uris(args: Args, opts: FixUris) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+    let sender = new_sender(&rpc, &args)?;
+    let update_authority = read_keypair_file(&opts.update_authority)?;
 
-            // let res = rpc.simulate_transaction(&tx);
-            // let res = res.expect("could not simulate tx");
-            // eprintln!("{:?}", res);
+    let fixes = read_fixes(&opts.plan)?;
+    let mints = fixes
+        .iter()
+        .map(|fix| fix.mint.parse())
+        .collect::<Result<Vec<Pubkey>, _>>()?;
+    let metadata = fetch_metadata_batch(&rpc, &mints)?;
 
-            let res = rpc.send_and_confirm_transaction(&tx);
-            let sig = res.expect("could not confirm tx");
-            eprintln!("{:?}", sig);
+    let journal = Journal::open(&db, opts.job.as_deref().unwrap_or("fix-uris"))?;
+    journal.resolve_sent(&sender)?;
+
+    for ((fix, mint), metadata) in fixes.iter().zip(&mints).zip(metadata) {
+        if journal.is_done(&fix.mint)? {
+            continue;
         }
+        journal.plan(&fix.mint, &format!("set uri {}", fix.uri))?;
+        let outcome = match metadata {
+            Some(metadata) => apply_fix(&sender, &journal, &update_authority, mint, &metadata, fix),
+            None => Err(format!("{} has no metadata account", mint).into()),
+        };
+        eprintln!("{} {:?}", fix.mint, outcome);
+        journal.record(&fix.mint, &outcome)?;
     }
 
-    Ok(())
+    journal.finish()
+}
+
+fn new_sender<'a>(rpc: &'a RpcClient, args: &Args) -> Result<Sender<'a>, Box<dyn Error>> {
+    Ok(Sender::new(rpc, args.dry_run)
+        .with_commitment(args.commitment.parse()?)
+        .with_retries(args.retries))
 }
 
 async fn load_blanks(opts: LoadBlanks) -> Result<(), Box<dyn Error>> {
-    let db = Connection::open(opts.db)?;
-    db.execute("DROP TABLE IF EXISTS blanks", params![])?;
-    db.execute(
-        "CREATE TABLE blanks (
-             mono_mint   text primary key,
-             mirc_name   text unique,
-             mirc_number numeric unique
-        )",
-        params![],
-    )?;
+    let db = schema::open(&opts.db)?;
 
     let file = File::open(opts.csv_file)?;
     let mut rdr = csv::Reader::from_reader(BufReader::new(file));
@@ -197,7 +472,9 @@ async fn load_blanks(opts: LoadBlanks) -> Result<(), Box<dyn Error>> {
         db.execute(
             "INSERT INTO blanks
             (mono_mint, mirc_name, mirc_number) values
-            (       ?1,        ?2,          ?3)",
+            (       ?1,        ?2,          ?3)
+            ON CONFLICT (mono_mint) DO UPDATE
+            SET mirc_name = excluded.mirc_name, mirc_number = excluded.mirc_number",
             params![mono_mint, mirc_name, mirc_number,],
         )?;
     }
@@ -206,81 +483,236 @@ async fn load_blanks(opts: LoadBlanks) -> Result<(), Box<dyn Error>> {
 }
 
 async fn plan_entanglements(opts: PlanEntanglements) -> Result<(), Box<dyn Error>> {
-    let db = Connection::open(opts.db)?;
-    db.execute("DROP TABLE IF EXISTS entanglements", params![])?;
-    db.execute(
-        "CREATE TABLE entanglements (
-             mirc_mint_address text primary key,
-             mono_mint_address text unique
-        )",
-        params![],
-    )?;
-
-    let mut stmt = db.prepare(
-        "SELECT mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri
-             FROM mirc_mints
-             ORDER BY mint_address",
-    )?;
+    let mut db = schema::open(&opts.db)?;
 
-    let mirc_mint_iter = stmt.query_map([], |row| try_mint_row(row))?;
-    for mirc_row in mirc_mint_iter {
-        let mirc_row = mirc_row.unwrap();
-
-        let mono_row = db.query_row(
-            "SELECT mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri
-                 FROM mono_mints
-                 WHERE inmate_number like ?1
-                 LIMIT 1",
-            params![mirc_row.inmate_number],
-            |row| try_mint_row(row),
-        );
+    let (pairs, findings) = pair_mints(&db)?;
+    print_findings(&findings);
+    if !findings.is_empty() && !opts.allow_partial {
+        return Err("not writing a partial plan, pass --allow-partial to write it anyway".into());
+    }
 
-        if let Ok(mono_row) = mono_row {
-            // normal entanglement
-            db.execute(
-                "INSERT INTO entanglements
-                     (mirc_mint_address, mono_mint_address) values
-                     (               ?1,                ?2)",
-                params![mirc_row.mint_address, mono_row.mint_address,],
+    // the new plan replaces the old one, unless pairs it leaves out are kept
+    let tx = db.transaction()?;
+    for (mirc, mono) in &pairs {
+        tx.execute(
+            "DELETE FROM entanglements
+                 WHERE (mirc_mint_address = ?1 OR mono_mint_address = ?2)
+                     AND NOT (mirc_mint_address = ?1 AND mono_mint_address = ?2)",
+            params![mirc, mono],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO entanglements
+            (mirc_mint_address, mono_mint_address) values
+            (               ?1,                ?2)",
+            params![mirc, mono],
+        )?;
+    }
+    let planned: HashSet<(&str, &str)> = pairs
+        .iter()
+        .map(|(mirc, mono)| (mirc.as_str(), mono.as_str()))
+        .collect();
+    let mut stmt = tx.prepare("SELECT mirc_mint_address, mono_mint_address FROM entanglements")?;
+    let unplanned = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|(mirc, mono)| !planned.contains(&(mirc.as_str(), mono.as_str())))
+        .collect::<Vec<_>>();
+    drop(stmt);
+    if opts.keep_unplanned {
+        if !unplanned.is_empty() {
+            eprintln!(
+                "kept {} entanglements from earlier plans that are not in this one",
+                unplanned.len()
+            );
+        }
+    } else {
+        for (mirc, mono) in &unplanned {
+            tx.execute(
+                "DELETE FROM entanglements WHERE mirc_mint_address = ?1 AND mono_mint_address = ?2",
+                params![mirc, mono],
             )?;
         }
+        if !unplanned.is_empty() {
+            eprintln!(
+                "removed {} entanglements from earlier plans that are not in this one",
+                unplanned.len()
+            );
+        }
     }
+    tx.commit()?;
+    eprintln!("planned {} entanglements", pairs.len());
+
+    Ok(())
+}
+
+/// A mirc mint address and the mono mint address it entangles with.
+type MintPair = (String, String);
+
+/// Pairs mirc and mono mints from the db: by inmate number, and blanks
+/// with the mirc ape named for their number. Mints that can't be paired one
+/// to one are left out and reported as findings.
+fn pair_mints(db: &Connection) -> Result<(Vec<MintPair>, Vec<Finding>), Box<dyn Error>> {
+    let read_mints = |table: &str| -> Result<Vec<MintRow>, rusqlite::Error> {
+        let mut stmt = db.prepare(&format!(
+            "SELECT mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri
+                 FROM {}
+                 ORDER BY mint_address",
+            table
+        ))?;
+        let rows = stmt.query_map([], |row| try_mint_row(row))?;
+        rows.collect()
+    };
+    let mirc_rows = read_mints("mirc_mints")?;
+    let mono_rows = read_mints("mono_mints")?;
 
     let mut stmt = db.prepare(
         "SELECT mono_mint, mirc_name, mirc_number
              FROM blanks
              ORDER BY mirc_number",
     )?;
+    let blank_rows = stmt
+        .query_map([], |row| try_blank_row(row))?
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let blanks_iter = stmt.query_map([], |row| try_blank_row(row))?;
-    for blank_row in blanks_iter {
-        let blank_row = blank_row.unwrap();
+    let mut findings = vec![];
+    let mut report = |collection: &str, mint: &str, check: &str, detail: String| {
+        findings.push(Finding {
+            collection: collection.to_string(),
+            mint: mint.to_string(),
+            check: check.to_string(),
+            detail,
+        })
+    };
+
+    let mut mono_by_number: HashMap<&str, Vec<&MintRow>> = HashMap::new();
+    for mono_row in &mono_rows {
+        if !mono_row.inmate_number.is_empty() {
+            mono_by_number
+                .entry(mono_row.inmate_number.as_str())
+                .or_default()
+                .push(mono_row);
+        }
+    }
+    for (number, rows) in &mono_by_number {
+        if rows.len() > 1 {
+            for row in rows {
+                report(
+                    "mono",
+                    &row.mint_address,
+                    "duplicate_inmate_number",
+                    format!("{} mono mints are inmate {}", rows.len(), number),
+                );
+            }
+        }
+    }
+
+    // normal entanglements pair apes with the same inmate number
+    let mut pairs: Vec<(&str, &str)> = vec![];
+    for mirc_row in &mirc_rows {
+        if let Some([mono_row]) = mono_by_number
+            .get(mirc_row.inmate_number.as_str())
+            .map(Vec::as_slice)
+        {
+            pairs.push((
+                mirc_row.mint_address.as_str(),
+                mono_row.mint_address.as_str(),
+            ));
+        }
+    }
+
+    // ghost entanglements pair a blank mono ape with the mirc ape named
+    // for its number
+    for blank_row in &blank_rows {
         let meta_name = format!("ExiledApe {}/518", blank_row.mirc_number);
+        let mirc_row = match mirc_rows.iter().find(|row| row.meta_name == meta_name) {
+            Some(mirc_row) => mirc_row,
+            None => {
+                report(
+                    "blank",
+                    &blank_row.mono_mint,
+                    "blank_without_mirc",
+                    format!("no mirc mint named {:?}", meta_name),
+                );
+                continue;
+            }
+        };
+        if let Some((_, mono)) = pairs
+            .iter()
+            .find(|(mirc, _)| *mirc == mirc_row.mint_address)
+        {
+            report(
+                "blank",
+                &blank_row.mono_mint,
+                "blank_collision",
+                format!(
+                    "{} already pairs {} with mono {}",
+                    meta_name, mirc_row.mint_address, mono
+                ),
+            );
+            continue;
+        }
+        pairs.push((mirc_row.mint_address.as_str(), blank_row.mono_mint.as_str()));
+    }
 
-        let mirc_row = db.query_row(
-            "SELECT mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri
-                 FROM mirc_mints
-                 WHERE meta_name like ?1
-                 LIMIT 1",
-            params![meta_name],
-            |row| try_mint_row(row),
-        );
-        let mirc_row = mirc_row.unwrap();
+    for mirc_row in &mirc_rows {
+        if !pairs.iter().any(|(mirc, _)| *mirc == mirc_row.mint_address) {
+            report(
+                "mirc",
+                &mirc_row.mint_address,
+                "unmatched",
+                format!(
+                    "{:?} inmate number {:?}",
+                    mirc_row.meta_name, mirc_row.inmate_number
+                ),
+            );
+        }
+    }
 
-        // ghost entanglement
-        db.execute(
-            "INSERT INTO entanglements
-            (mirc_mint_address, mono_mint_address) values
-            (               ?1,                ?2)",
-            params![mirc_row.mint_address, blank_row.mono_mint],
-        )?;
+    let mut mono_pairs: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (mirc, mono) in &pairs {
+        mono_pairs.entry(*mono).or_default().push(*mirc);
+    }
+    for (mono, mircs) in &mono_pairs {
+        if mircs.len() > 1 {
+            report(
+                "mono",
+                mono,
+                "one_to_one",
+                format!("paired with mirc {}", mircs.join(", ")),
+            );
+        }
     }
+    pairs.retain(|(_, mono)| mono_pairs[mono].len() == 1);
 
-    Ok(())
+    findings.sort_by(|a, b| (&a.check, &a.mint).cmp(&(&b.check, &b.mint)));
+
+    let pairs = pairs
+        .into_iter()
+        .map(|(mirc, mono)| (mirc.to_string(), mono.to_string()))
+        .collect();
+    Ok((pairs, findings))
 }
 
 async fn load_mints(opts: LoadMints) -> Result<(), Box<dyn Error>> {
-    let (x, y) = join!(load_mono_mints(opts.clone()), load_mirc_mints(opts.clone()));
+    let limiter = RateLimiter::new(opts.rps);
+    let cache = AssetCache::open(&opts.cache_dir, opts.offline, opts.refresh)?;
+    let loader = MintLoader {
+        db: &opts.db,
+        rpc: &opts.rpc,
+        concurrency: opts.concurrency,
+        limiter: &limiter,
+        cache: &cache,
+        mirror_images: opts.mirror_images,
+    };
+    let mono_mints = read_mint_file(&opts.mono_file)?;
+    let mirc_mints = read_mint_file(&opts.mirc_file)?;
+    let (x, y) = join!(
+        load_mono_mints(loader, &mono_mints),
+        load_mirc_mints(loader, &mirc_mints)
+    );
 
     if x.is_err() || y.is_err() {
         let mut msg: Vec<String> = vec![];
@@ -296,18 +728,6 @@ async fn load_mints(opts: LoadMints) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn find_metadata_address(mint: Pubkey) -> Pubkey {
-    let (metadata, _bump) = Pubkey::find_program_address(
-        &[
-            metaplex_token_metadata::state::PREFIX.as_bytes(),
-            metaplex_token_metadata::id().as_ref(),
-            mint.as_ref(),
-        ],
-        &metaplex_token_metadata::id(),
-    );
-    metadata
-}
-
 #[derive(Debug)]
 struct BlankRow {
     mono_mint: String,
@@ -349,46 +769,54 @@ fn try_mint_row(row: &rusqlite::Row) -> Result<MintRow, rusqlite::Error> {
     })
 }
 
-async fn load_mirc_mints(opts: LoadMints) -> Result<(), Box<dyn Error>> {
-    let rpc = RpcClient::new(opts.rpc);
-    let db = Connection::open(opts.db)?;
+/// What the mint loaders need besides the mints themselves.
+#[derive(Clone, Copy)]
+struct MintLoader<'a> {
+    db: &'a str,
+    rpc: &'a str,
+    concurrency: usize,
+    limiter: &'a RateLimiter,
+    cache: &'a AssetCache,
+    mirror_images: bool,
+}
+
+async fn load_mirc_mints(loader: MintLoader<'_>, mints: &[Pubkey]) -> Result<(), Box<dyn Error>> {
+    let rpc = RpcClient::new(loader.rpc.to_string());
+    let db = schema::open(loader.db)?;
 
-    db.execute("DROP TABLE IF EXISTS mirc_mints", params![])?;
-    db.execute(
-        "CREATE TABLE mirc_mints (
-             mint_address text primary key,
-             meta_address text unique,
-             meta_name text,
-             meta_uri text,
-             inmate_number text,
-             image_uri text
-         )",
-        params![],
-    )?;
-    let mirc_file = File::open(opts.mirc_file)?;
-    let mirc_reader = BufReader::new(mirc_file);
-    for line in mirc_reader.lines() {
-        let mint_address = line.unwrap().parse()?;
-        let meta_address = find_metadata_address(mint_address);
-        let metadata = rpc.get_account(&meta_address)?;
-        let metadata = Metadata::deserialize(&mut metadata.data())?;
-
-        let jm = reqwest::get(metadata.data.clone().uri)
-            .await?
-            .json::<MircMeta>()
-            .await?;
+    let metadata = fetch_metadata_cached(loader, &rpc, mints).await?;
+
+    let client = reqwest::Client::new();
+    let json = stream::iter(&metadata)
+        .map(|metadata| fetch_json_cached::<MircMeta>(loader, &client, &metadata.data.uri))
+        .buffered(loader.concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    if loader.mirror_images {
+        let images: Vec<&str> = json.iter().flatten().map(|jm| jm.image.as_str()).collect();
+        mirror_images(loader, &client, &images).await;
+    }
+
+    for ((mint_address, metadata), jm) in mints.iter().zip(metadata).zip(json) {
+        let meta_address = find_metadata_address(*mint_address);
+        let jm = jm?;
 
         let mut inmate_number = "".to_string();
-        for attribute in jm.attributes {
+        for attribute in &jm.attributes {
             if attribute.trait_type == "Inmate number" {
-                inmate_number = attribute.value;
+                inmate_number = attribute.value.clone();
             }
         }
+        insert_attributes(&db, mint_address, &jm.attributes)?;
 
         db.execute(
             "INSERT INTO mirc_mints
             (mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri) values
-            (          ?1,           ?2,        ?3,       ?4,            ?5,        ?6)",
+            (          ?1,           ?2,        ?3,       ?4,            ?5,        ?6)
+            ON CONFLICT (mint_address) DO UPDATE
+            SET meta_address = excluded.meta_address, meta_name = excluded.meta_name,
+                meta_uri = excluded.meta_uri, inmate_number = excluded.inmate_number,
+                image_uri = excluded.image_uri",
             params![
                 mint_address.to_string(),
                 meta_address.to_string(),
@@ -403,49 +831,47 @@ async fn load_mirc_mints(opts: LoadMints) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn load_mono_mints(opts: LoadMints) -> Result<(), Box<dyn Error>> {
-    let rpc = RpcClient::new(opts.rpc);
-    let db = Connection::open(opts.db)?;
+async fn load_mono_mints(loader: MintLoader<'_>, mints: &[Pubkey]) -> Result<(), Box<dyn Error>> {
+    let rpc = RpcClient::new(loader.rpc.to_string());
+    let db = schema::open(loader.db)?;
 
-    db.execute("DROP TABLE IF EXISTS mono_mints", params![])?;
-    db.execute(
-        "CREATE TABLE mono_mints (
-             mint_address text primary key,
-             meta_address text unique,
-             meta_name text,
-             meta_uri text,
-             inmate_number text,
-             image_uri text,
-             genesis_order integer
-         )",
-        params![],
-    )?;
-    let mono_file = File::open(opts.mono_file)?;
-    let mono_reader = BufReader::new(mono_file);
+    let metadata = fetch_metadata_cached(loader, &rpc, mints).await?;
+
+    let client = reqwest::Client::new();
+    let json = stream::iter(&metadata)
+        .map(|metadata| fetch_json_cached::<MonoMeta>(loader, &client, &metadata.data.uri))
+        .buffered(loader.concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    if loader.mirror_images {
+        let images: Vec<&str> = json.iter().flatten().map(|jm| jm.image.as_str()).collect();
+        mirror_images(loader, &client, &images).await;
+    }
 
     let mut genesis_order = 0;
-    for line in mono_reader.lines() {
+    for ((mint_address, metadata), jm) in mints.iter().zip(metadata).zip(json) {
         genesis_order = genesis_order + 1;
 
-        let mint_address = line.unwrap().parse()?;
-        let meta_address = find_metadata_address(mint_address);
-        let metadata = rpc.get_account(&meta_address)?;
-        let metadata = Metadata::deserialize(&mut metadata.data())?;
+        let meta_address = find_metadata_address(*mint_address);
         let inmate_number = metadata.data.name.strip_prefix("Degen Ape #").unwrap_or("");
 
         let mut image = "".to_string();
-        match reqwest::get(metadata.data.clone().uri).await {
-            Err(e) => eprintln!("a {:?}", e),
-            Ok(res) => match res.json::<MonoMeta>().await {
-                Ok(jm) => image = jm.image,
-                Err(e) => eprintln!("b {:?}", e),
-            },
+        match jm {
+            Ok(jm) => {
+                insert_attributes(&db, mint_address, &jm.attributes)?;
+                image = jm.image;
+            }
+            Err(e) => eprintln!("{} {:?}", mint_address, e),
         }
 
         db.execute(
             "INSERT INTO mono_mints
             (mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri, genesis_order) values
-            (          ?1,           ?2,        ?3,       ?4,            ?5,        ?6,            ?7)",
+            (          ?1,           ?2,        ?3,       ?4,            ?5,        ?6,            ?7)
+            ON CONFLICT (mint_address) DO UPDATE
+            SET meta_address = excluded.meta_address, meta_name = excluded.meta_name,
+                meta_uri = excluded.meta_uri, inmate_number = excluded.inmate_number,
+                image_uri = excluded.image_uri, genesis_order = excluded.genesis_order",
             params![
                 mint_address.to_string(),
                 meta_address.to_string(),
@@ -460,3 +886,430 @@ async fn load_mono_mints(opts: LoadMints) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Replaces the stored attributes of `mint`.
+fn insert_attributes(
+    db: &Connection,
+    mint: &Pubkey,
+    attributes: &[Attribute],
+) -> Result<(), Box<dyn Error>> {
+    db.execute(
+        "DELETE FROM attributes WHERE mint_address = ?1",
+        params![mint.to_string()],
+    )?;
+    for attribute in attributes {
+        db.execute(
+            "INSERT INTO attributes
+            (mint_address, trait_type, value) values
+            (          ?1,         ?2,    ?3)",
+            params![mint.to_string(), attribute.trait_type, attribute.value],
+        )?;
+    }
+    Ok(())
+}
+
+fn read_mint_file(path: &str) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut mints = vec![];
+    for line in reader.lines() {
+        mints.push(line?.parse()?);
+    }
+    Ok(mints)
+}
+
+/// Fetches metadata a hundred accounts per request, one request per
+/// limiter slot.
+async fn fetch_metadata_limited(
+    rpc: &RpcClient,
+    mints: &[Pubkey],
+    limiter: &RateLimiter,
+) -> Result<Vec<Metadata>, Box<dyn Error>> {
+    let mut metadata = Vec::with_capacity(mints.len());
+    for chunk in mints.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        limiter.wait().await;
+        for (mint, m) in chunk.iter().zip(fetch_metadata_batch(rpc, chunk)?) {
+            metadata.push(m.ok_or_else(|| format!("{} has no metadata account", mint))?);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Metadata for `mints`, read from the asset cache where it has them,
+/// unless refreshing, and fetched and saved to it otherwise.
+async fn fetch_metadata_cached(
+    loader: MintLoader<'_>,
+    rpc: &RpcClient,
+    mints: &[Pubkey],
+) -> Result<Vec<Metadata>, Box<dyn Error>> {
+    let mut metadata = Vec::with_capacity(mints.len());
+    let mut missing = vec![];
+    for mint in mints {
+        let cached = if loader.cache.refresh() && !loader.cache.offline() {
+            None
+        } else {
+            loader.cache.account(&find_metadata_address(*mint))?
+        };
+        match cached {
+            Some(data) => metadata.push(Some(decode_metadata(&data)?)),
+            None if loader.cache.offline() => {
+                return Err(format!("metadata of {} is not cached", mint).into())
+            }
+            None => {
+                metadata.push(None);
+                missing.push(*mint);
+            }
+        }
+    }
+
+    let mut fetched = fetch_metadata_limited(rpc, &missing, loader.limiter)
+        .await?
+        .into_iter();
+    for (mint, metadata) in mints.iter().zip(&mut metadata) {
+        if metadata.is_none() {
+            let m = fetched.next().ok_or("fewer metadata accounts than mints")?;
+            loader
+                .cache
+                .put_account(&find_metadata_address(*mint), &m.try_to_vec()?)?;
+            *metadata = Some(m);
+        }
+    }
+    Ok(metadata.into_iter().flatten().collect())
+}
+
+async fn fetch_json_cached<T: DeserializeOwned>(
+    loader: MintLoader<'_>,
+    client: &reqwest::Client,
+    uri: &str,
+) -> Result<T, Box<dyn Error>> {
+    let body = loader.cache.fetch(client, loader.limiter, uri).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Fetches `images` into the asset cache, reporting any that fail.
+async fn mirror_images(loader: MintLoader<'_>, client: &reqwest::Client, images: &[&str]) {
+    let results = stream::iter(images)
+        .map(|image| async move {
+            let result = loader.cache.fetch(client, loader.limiter, image).await;
+            (image, result)
+        })
+        .buffered(loader.concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    for (image, result) in results {
+        if let Err(e) = result {
+            eprintln!("{} {}", image, e);
+        }
+    }
+}
+
+async fn fetch_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    limiter: &RateLimiter,
+    uri: &str,
+) -> Result<T, reqwest::Error> {
+    limiter.wait().await;
+    client.get(uri).send().await?.json::<T>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpl_token_metadata::state::{Data, Key, MAX_NAME_LENGTH, MAX_URI_LENGTH};
+    use std::{fs, path::PathBuf};
+
+    fn pad(s: &str, len: usize) -> String {
+        format!("{}{}", s, "\0".repeat(len - s.len()))
+    }
+
+    /// A metadata account as the program lays it out, padding included.
+    fn metadata_account(mint: &Pubkey, name: &str, uri: &str) -> Vec<u8> {
+        let data = Data {
+            name: pad(name, MAX_NAME_LENGTH),
+            symbol: "".to_string(),
+            uri: pad(uri, MAX_URI_LENGTH),
+            seller_fee_basis_points: 500,
+            creators: None,
+        };
+        let mut account = vec![Key::MetadataV1 as u8];
+        account.extend(Pubkey::new_unique().to_bytes());
+        account.extend(mint.to_bytes());
+        account.extend(data.try_to_vec().unwrap());
+        account.extend([0, 1]);
+        account.resize(account.len() + 64, 0);
+        account
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xape-miner-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rebuilds_the_mint_tables_from_the_cache_alone() {
+        let dir = scratch_dir("offline");
+        let cache = AssetCache::open(dir.join("cache").to_str().unwrap(), true, false).unwrap();
+        let db = dir.join("xapes.db");
+
+        let mirc = Pubkey::new_unique();
+        let mirc_uri = "https://arweave.net/mirc-7";
+        cache
+            .put_account(
+                &find_metadata_address(mirc),
+                &metadata_account(&mirc, "MIRC #7", mirc_uri),
+            )
+            .unwrap();
+        let mirc_json = r#"{
+            "name": "MIRC #7",
+            "image": "https://arweave.net/mirc-7.png",
+            "attributes": [
+                { "trait_type": "Inmate number", "value": "7" },
+                { "trait_type": "Cell", "value": 3 }
+            ]
+        }"#;
+        cache.put(mirc_uri, mirc_json.as_bytes(), None).unwrap();
+
+        let mono = Pubkey::new_unique();
+        let mono_uri = "https://arweave.net/mono-12";
+        cache
+            .put_account(
+                &find_metadata_address(mono),
+                &metadata_account(&mono, "Degen Ape #12", mono_uri),
+            )
+            .unwrap();
+        let mono_json =
+            r#"{ "name": "Degen Ape #12", "image": "https://arweave.net/mono-12.png" }"#;
+        cache.put(mono_uri, mono_json.as_bytes(), None).unwrap();
+
+        let limiter = RateLimiter::new(10);
+        let loader = MintLoader {
+            db: db.to_str().unwrap(),
+            rpc: "http://127.0.0.1:1",
+            concurrency: 2,
+            limiter: &limiter,
+            cache: &cache,
+            mirror_images: false,
+        };
+        load_mirc_mints(loader, &[mirc]).await.unwrap();
+        load_mono_mints(loader, &[mono]).await.unwrap();
+        // loading again from the same cache changes nothing, and online
+        // the cache is still used without going to the network
+        load_mirc_mints(loader, &[mirc]).await.unwrap();
+        let online = AssetCache::open(dir.join("cache").to_str().unwrap(), false, false).unwrap();
+        load_mirc_mints(
+            MintLoader {
+                cache: &online,
+                ..loader
+            },
+            &[mirc],
+        )
+        .await
+        .unwrap();
+
+        let db = schema::open(loader.db).unwrap();
+        let (name, uri, inmate_number, image): (String, String, String, String) = db
+            .query_row(
+                "SELECT meta_name, meta_uri, inmate_number, image_uri FROM mirc_mints
+                 WHERE mint_address = ?1",
+                params![mirc.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(name, "MIRC #7");
+        assert_eq!(uri, mirc_uri);
+        assert_eq!(inmate_number, "7");
+        assert_eq!(image, "https://arweave.net/mirc-7.png");
+        let cell: String = db
+            .query_row(
+                "SELECT value FROM attributes WHERE mint_address = ?1 AND trait_type = 'Cell'",
+                params![mirc.to_string()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(cell, "3");
+        let attributes: i64 = db
+            .query_row("SELECT count(*) FROM attributes", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(attributes, 2);
+
+        let (inmate_number, genesis_order): (String, i64) = db
+            .query_row(
+                "SELECT inmate_number, genesis_order FROM mono_mints WHERE mint_address = ?1",
+                params![mono.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(inmate_number, "12");
+        assert_eq!(genesis_order, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn offline_loads_fail_on_what_the_cache_lacks() {
+        let dir = scratch_dir("offline-missing");
+        let cache = AssetCache::open(dir.join("cache").to_str().unwrap(), true, false).unwrap();
+        let db = dir.join("xapes.db");
+        let limiter = RateLimiter::new(10);
+        let loader = MintLoader {
+            db: db.to_str().unwrap(),
+            rpc: "http://127.0.0.1:1",
+            concurrency: 2,
+            limiter: &limiter,
+            cache: &cache,
+            mirror_images: false,
+        };
+
+        let mirc = Pubkey::new_unique();
+        assert!(load_mirc_mints(loader, &[mirc]).await.is_err());
+
+        cache
+            .put_account(
+                &find_metadata_address(mirc),
+                &metadata_account(&mirc, "MIRC #7", "https://arweave.net/mirc-7"),
+            )
+            .unwrap();
+        assert!(load_mirc_mints(loader, &[mirc]).await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn mint_db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        schema::migrate(&mut db).unwrap();
+        db
+    }
+
+    fn insert_mint(db: &Connection, table: &str, mint: &str, name: &str, inmate_number: &str) {
+        db.execute(
+            &format!(
+                "INSERT INTO {}
+                (mint_address, meta_address, meta_name, meta_uri, inmate_number, image_uri) values
+                (          ?1,           ?1,        ?2,       '',            ?3,        '')",
+                table
+            ),
+            params![mint, name, inmate_number],
+        )
+        .unwrap();
+    }
+
+    fn insert_blank(db: &Connection, mono: &str, mirc_number: u32) {
+        db.execute(
+            "INSERT INTO blanks
+            (mono_mint, mirc_name, mirc_number) values
+            (       ?1,        ?2,          ?3)",
+            params![mono, format!("Blank {}", mirc_number), mirc_number],
+        )
+        .unwrap();
+    }
+
+    fn checks(findings: &[Finding]) -> Vec<(&str, &str)> {
+        findings
+            .iter()
+            .map(|f| (f.check.as_str(), f.mint.as_str()))
+            .collect()
+    }
+
+    fn pair(mirc: &str, mono: &str) -> (String, String) {
+        (mirc.to_string(), mono.to_string())
+    }
+
+    #[test]
+    fn pairs_by_inmate_number_and_blanks_by_name() {
+        let db = mint_db();
+        insert_mint(&db, "mirc_mints", "mirc-1", "ExiledApe 1/518", "100");
+        insert_mint(&db, "mono_mints", "mono-100", "Degen Ape #100", "100");
+        // two mono apes claim inmate 300, so its mirc ape stays unpaired
+        insert_mint(&db, "mirc_mints", "mirc-3", "ExiledApe 3/518", "300");
+        insert_mint(&db, "mono_mints", "mono-300a", "Degen Ape #300", "300");
+        insert_mint(&db, "mono_mints", "mono-300b", "Degen Ape #300", "300");
+        // no mono ape is inmate 400
+        insert_mint(&db, "mirc_mints", "mirc-4", "ExiledApe 4/518", "400");
+        // blanks pair with the mirc ape named for their number
+        insert_mint(&db, "mirc_mints", "mirc-5", "ExiledApe 5/518", "");
+        insert_blank(&db, "blank-5", 5);
+        insert_blank(&db, "blank-1", 1);
+        insert_blank(&db, "blank-9", 9);
+
+        let (mut pairs, findings) = pair_mints(&db).unwrap();
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            [pair("mirc-1", "mono-100"), pair("mirc-5", "blank-5")]
+        );
+        assert_eq!(
+            checks(&findings),
+            [
+                ("blank_collision", "blank-1"),
+                ("blank_without_mirc", "blank-9"),
+                ("duplicate_inmate_number", "mono-300a"),
+                ("duplicate_inmate_number", "mono-300b"),
+                ("unmatched", "mirc-3"),
+                ("unmatched", "mirc-4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_mono_apes_paired_twice() {
+        let db = mint_db();
+        insert_mint(&db, "mirc_mints", "mirc-1", "ExiledApe 1/518", "100");
+        insert_mint(&db, "mirc_mints", "mirc-2", "ExiledApe 2/518", "200");
+        insert_mint(&db, "mono_mints", "mono-100", "Degen Ape #100", "100");
+        insert_blank(&db, "mono-100", 2);
+
+        let (pairs, findings) = pair_mints(&db).unwrap();
+        assert!(pairs.is_empty());
+        assert_eq!(checks(&findings), [("one_to_one", "mono-100")]);
+    }
+
+    #[tokio::test]
+    async fn a_new_plan_replaces_the_old_one() {
+        let dir = scratch_dir("plan");
+        let path = dir.join("xapes.db");
+        let db = schema::open(path.to_str().unwrap()).unwrap();
+        insert_mint(&db, "mirc_mints", "mirc-1", "ExiledApe 1/518", "100");
+        insert_mint(&db, "mono_mints", "mono-100", "Degen Ape #100", "100");
+        let plan = |keep_unplanned| PlanEntanglements {
+            db: path.to_str().unwrap().to_string(),
+            allow_partial: false,
+            keep_unplanned,
+        };
+        let entanglements = |db: &Connection| -> Vec<(String, String)> {
+            let mut stmt = db
+                .prepare(
+                    "SELECT mirc_mint_address, mono_mint_address FROM entanglements
+                         ORDER BY mirc_mint_address",
+                )
+                .unwrap();
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            rows
+        };
+
+        let stale = "INSERT INTO entanglements
+            (mirc_mint_address, mono_mint_address) values
+            (     'mirc-stale',      'mono-stale')";
+        db.execute(stale, []).unwrap();
+        plan_entanglements(plan(true)).await.unwrap();
+        assert_eq!(
+            entanglements(&db),
+            [pair("mirc-1", "mono-100"), pair("mirc-stale", "mono-stale")]
+        );
+
+        plan_entanglements(plan(false)).await.unwrap();
+        assert_eq!(entanglements(&db), [pair("mirc-1", "mono-100")]);
+
+        // a plan with findings is refused unless partial plans are allowed
+        insert_mint(&db, "mirc_mints", "mirc-4", "ExiledApe 4/518", "400");
+        assert!(plan_entanglements(plan(false)).await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//! Trait frequencies and rarity rankings from the `attributes` table.
+//!
+//! A mint without some trait the rest of its collection has counts as
+//! having the value `None` for it, so missing traits weigh in too. Traits
+//! whose every value is unique, like the inmate number, say nothing about
+//! rarity and are left out.
+//!
+//! Two rankings are kept: the rarity score, the sum over traits of one over
+//! the trait value's frequency, and statistical rarity, the product of the
+//! frequencies. Each has its own rank: `rarity_rank` 1 is the highest
+//! score and `statistical_rank` 1 the lowest statistical rarity, both the
+//! rarest by their measure.
+
+use crate::Rarity;
+use rusqlite::params;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+};
+use xapes_core::schema;
+
+const NONE: &str = "None";
+
+/// Each mint's trait values, by trait type.
+type Traits = HashMap<String, BTreeMap<String, String>>;
+
+struct RankedMint {
+    mint: String,
+    name: String,
+    rarity_score: f64,
+    statistical_rarity: f64,
+    statistical_rank: usize,
+}
+
+pub async fn rarity(opts: Rarity) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+
+    let collections: &[&str] = match opts.collection.as_deref() {
+        Some("mirc") => &["mirc"],
+        Some("mono") => &["mono"],
+        Some(other) => return Err(format!("unknown collection {:?}", other).into()),
+        None => &["mirc", "mono"],
+    };
+
+    for &collection in collections {
+        let mut stmt = db.prepare(&format!(
+            "SELECT mint_address, meta_name FROM {}_mints ORDER BY mint_address",
+            collection
+        ))?;
+        let names: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+
+        let mut stmt = db.prepare(&format!(
+            "SELECT attributes.mint_address, attributes.trait_type, attributes.value
+                 FROM attributes
+                 JOIN {0}_mints ON {0}_mints.mint_address = attributes.mint_address",
+            collection
+        ))?;
+        let mut traits: Traits = HashMap::new();
+        for row in stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })? {
+            let (mint, trait_type, value) = row?;
+            traits.entry(mint).or_default().insert(trait_type, value);
+        }
+
+        let frequencies = trait_frequencies(&names, &traits);
+        let total = names.len() as f64;
+
+        let ranked = rank_mints(names, &traits, &frequencies);
+
+        let tx = db.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM trait_frequencies WHERE collection = ?1",
+            params![collection],
+        )?;
+        for (trait_type, values) in &frequencies {
+            for (value, count) in values {
+                tx.execute(
+                    "INSERT INTO trait_frequencies
+                    (collection, trait_type, value, count, frequency) values
+                    (        ?1,         ?2,    ?3,    ?4,        ?5)",
+                    params![
+                        collection,
+                        trait_type,
+                        value,
+                        *count as i64,
+                        *count as f64 / total
+                    ],
+                )?;
+            }
+        }
+        tx.execute(
+            "DELETE FROM rarity WHERE collection = ?1",
+            params![collection],
+        )?;
+        for (rank, mint) in ranked.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO rarity
+                (mint_address, collection, rarity_rank, rarity_score, statistical_rarity, statistical_rank) values
+                (          ?1,         ?2,          ?3,           ?4,                 ?5,               ?6)",
+                params![
+                    mint.mint,
+                    collection,
+                    rank as i64 + 1,
+                    mint.rarity_score,
+                    mint.statistical_rarity,
+                    mint.statistical_rank as i64
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        println!("{} ({} mints)", collection, ranked.len());
+        for (trait_type, values) in &frequencies {
+            println!("  {}", trait_type);
+            for (value, count) in values {
+                println!(
+                    "    {:<30} {:>5} {:>6.2}%",
+                    value,
+                    count,
+                    *count as f64 / total * 100.0
+                );
+            }
+        }
+        for (rank, mint) in ranked.iter().take(opts.top).enumerate() {
+            println!(
+                "  {:>4} {:<44} {:<24} {:>10.2} {:>4} {:.3e}",
+                rank + 1,
+                mint.mint,
+                mint.name,
+                mint.rarity_score,
+                mint.statistical_rank,
+                mint.statistical_rarity
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Scores `names`, returned rarest first by rarity score, each with its
+/// rank by statistical rarity. Mints that tie keep the order of `names`
+/// under both rankings.
+fn rank_mints(
+    names: Vec<(String, String)>,
+    traits: &Traits,
+    frequencies: &BTreeMap<String, BTreeMap<String, usize>>,
+) -> Vec<RankedMint> {
+    let total = names.len() as f64;
+    let mut ranked: Vec<RankedMint> = names
+        .into_iter()
+        .map(|(mint, name)| {
+            let mut rarity_score = 0.0;
+            let mut statistical_rarity = 1.0;
+            for (trait_type, values) in frequencies {
+                let value = traits
+                    .get(&mint)
+                    .and_then(|t| t.get(trait_type))
+                    .map(String::as_str)
+                    .unwrap_or(NONE);
+                let frequency = values[value] as f64 / total;
+                rarity_score += 1.0 / frequency;
+                statistical_rarity *= frequency;
+            }
+            RankedMint {
+                mint,
+                name,
+                rarity_score,
+                statistical_rarity,
+                statistical_rank: 0,
+            }
+        })
+        .collect();
+
+    let mut by_statistical_rarity: Vec<usize> = (0..ranked.len()).collect();
+    by_statistical_rarity.sort_by(|&a, &b| {
+        ranked[a]
+            .statistical_rarity
+            .partial_cmp(&ranked[b].statistical_rarity)
+            .unwrap_or(Ordering::Equal)
+    });
+    for (rank, index) in by_statistical_rarity.into_iter().enumerate() {
+        ranked[index].statistical_rank = rank + 1;
+    }
+    ranked.sort_by(|a, b| {
+        b.rarity_score
+            .partial_cmp(&a.rarity_score)
+            .unwrap_or(Ordering::Equal)
+    });
+    ranked
+}
+
+/// How many mints have each value of each trait, counting mints without
+/// the trait as `None`, and leaving out traits where no two mints share a
+/// value.
+fn trait_frequencies(
+    names: &[(String, String)],
+    traits: &Traits,
+) -> BTreeMap<String, BTreeMap<String, usize>> {
+    let trait_types: BTreeSet<&String> = traits.values().flat_map(|t| t.keys()).collect();
+
+    let mut frequencies = BTreeMap::new();
+    for trait_type in trait_types {
+        let mut values: BTreeMap<String, usize> = BTreeMap::new();
+        for (mint, _) in names {
+            let value = traits
+                .get(mint)
+                .and_then(|t| t.get(trait_type))
+                .map(String::as_str)
+                .unwrap_or(NONE);
+            *values.entry(value.to_string()).or_default() += 1;
+        }
+        if values.len() < names.len() {
+            frequencies.insert(trait_type.clone(), values);
+        }
+    }
+    frequencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mints named `m0`, `m1`, ... with the given trait values, `None`
+    /// where a mint lacks the trait.
+    fn collection(
+        table: &[(&str, &[Option<&str>])],
+    ) -> (Vec<(String, String)>, Traits) {
+        let count = table[0].1.len();
+        let names = (0..count)
+            .map(|i| (format!("m{}", i), format!("Ape #{}", i)))
+            .collect();
+        let mut traits: Traits = HashMap::new();
+        for (trait_type, values) in table {
+            for (i, value) in values.iter().enumerate() {
+                if let Some(value) = value {
+                    traits
+                        .entry(format!("m{}", i))
+                        .or_default()
+                        .insert(trait_type.to_string(), value.to_string());
+                }
+            }
+        }
+        (names, traits)
+    }
+
+    fn counts(values: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        values
+            .iter()
+            .map(|(value, count)| (value.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn counts_missing_traits_and_drops_unique_ones() {
+        let (names, traits) = collection(&[
+            (
+                "Background",
+                &[Some("Red"), Some("Red"), Some("Blue"), None],
+            ),
+            ("Hat", &[Some("Cap"), None, None, None]),
+            (
+                "Inmate number",
+                &[Some("1"), Some("2"), Some("3"), Some("4")],
+            ),
+        ]);
+        let frequencies = trait_frequencies(&names, &traits);
+        assert_eq!(frequencies.len(), 2);
+        assert_eq!(
+            frequencies["Background"],
+            counts(&[("Blue", 1), ("None", 1), ("Red", 2)])
+        );
+        assert_eq!(frequencies["Hat"], counts(&[("Cap", 1), ("None", 3)]));
+    }
+
+    /// Ten mints, where those in `holders` have the `rare` value.
+    fn held_by(
+        holders: &[usize],
+        rare: &'static str,
+        common: &'static str,
+    ) -> Vec<Option<&'static str>> {
+        (0..10)
+            .map(|i| Some(if holders.contains(&i) { rare } else { common }))
+            .collect()
+    }
+
+    #[test]
+    fn ranks_by_score_and_statistical_rarity() {
+        // m0 has the one rarest trait, m1 and m2 two less rare ones: m0
+        // scores highest, m1 and m2 are rarer statistically
+        let eyes = held_by(&[0], "Laser", "Plain");
+        let fur = held_by(&[1, 2], "Gold", "Brown");
+        let hat = held_by(&[1, 2], "Crown", "Cap");
+        let (names, traits) =
+            collection(&[("Eyes", &eyes[..]), ("Fur", &fur[..]), ("Hat", &hat[..])]);
+        let frequencies = trait_frequencies(&names, &traits);
+        assert_eq!(frequencies["Eyes"], counts(&[("Laser", 1), ("Plain", 9)]));
+        assert_eq!(frequencies["Fur"], counts(&[("Brown", 8), ("Gold", 2)]));
+
+        let ranked = rank_mints(names, &traits, &frequencies);
+        let order: Vec<(&str, usize)> = ranked
+            .iter()
+            .map(|mint| (mint.mint.as_str(), mint.statistical_rank))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("m0", 3),
+                ("m1", 1),
+                ("m2", 2),
+                ("m3", 4),
+                ("m4", 5),
+                ("m5", 6),
+                ("m6", 7),
+                ("m7", 8),
+                ("m8", 9),
+                ("m9", 10),
+            ]
+        );
+        assert!((ranked[0].rarity_score - 12.5).abs() < 1e-9);
+        assert!((ranked[1].statistical_rarity - 0.036).abs() < 1e-9);
+        assert_eq!(ranked[1].rarity_score, ranked[2].rarity_score);
+        assert_eq!(ranked[3].rarity_score, ranked[9].rarity_score);
+    }
+}
@@ -0,0 +1,270 @@
+//! Royalty income, read from the transaction history of the creator
+//! addresses rather than guessed from the custody balance.
+//!
+//! A marketplace sale pays each creator its share in the same transaction
+//! that moves the token, so a transaction that raised a creator's balance
+//! and touched a token account of one of our mints is taken as a royalty
+//! on that mint.
+
+use crate::{rate_limit::RateLimiter, ScanRoyalties};
+use rusqlite::{params, Connection, OptionalExtension};
+use solana_client::{
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_response::RpcConfirmedTransactionStatusWithSignature,
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, native_token::lamports_to_sol, pubkey::Pubkey,
+    signature::Signature,
+};
+use solana_transaction_status::{EncodedConfirmedTransaction, UiTransactionEncoding};
+use std::{collections::HashMap, error::Error};
+use xapes_core::{policy::RoyaltyPolicy, schema};
+
+pub async fn scan_royalties(opts: ScanRoyalties) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+    let limiter = RateLimiter::new(opts.rps);
+
+    let collections = read_collections(&db)?;
+    let addresses = if opts.address.is_empty() {
+        creator_addresses(&RoyaltyPolicy::read(&opts.policy)?)?
+    } else {
+        opts.address
+            .iter()
+            .map(|address| address.parse())
+            .collect::<Result<Vec<Pubkey>, _>>()?
+    };
+
+    for address in addresses {
+        let newest: Option<String> = db
+            .query_row(
+                "SELECT newest_signature FROM royalty_scans WHERE address = ?1",
+                params![address.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let until = match newest {
+            Some(sig) => Some(sig.parse()?),
+            None => None,
+        };
+        let signatures = fetch_signatures(&rpc, &limiter, &address, until).await?;
+
+        let mut payments = 0;
+        for status in signatures.iter().rev() {
+            if status.err.is_some() {
+                continue;
+            }
+            let sig: Signature = status.signature.parse()?;
+            limiter.wait().await;
+            let tx = rpc.get_transaction(&sig, UiTransactionEncoding::Base64)?;
+            let payment = match royalty_payment(&tx, &address, &collections) {
+                Some(payment) => payment,
+                None => continue,
+            };
+            db.execute(
+                "INSERT OR REPLACE INTO royalty_payments (
+                     signature, address, mint, collection, lamports, slot, block_time
+                )
+                values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    status.signature,
+                    address.to_string(),
+                    payment.mint,
+                    payment.collection,
+                    payment.lamports as i64,
+                    tx.slot as i64,
+                    tx.block_time
+                ],
+            )?;
+            payments += 1;
+        }
+
+        if let Some(newest) = signatures.first() {
+            db.execute(
+                "INSERT INTO royalty_scans (address, newest_signature, scanned_at)
+                     VALUES (?1, ?2, datetime('now'))
+                     ON CONFLICT (address) DO UPDATE
+                     SET newest_signature = ?2, scanned_at = datetime('now')",
+                params![address.to_string(), newest.signature],
+            )?;
+        }
+        eprintln!(
+            "{} {} new transactions, {} royalty payments",
+            address,
+            signatures.len(),
+            payments
+        );
+    }
+
+    print_income(&db, opts.since.as_deref())
+}
+
+/// Every mint in `mirc_mints` and `mono_mints`, with its collection.
+fn read_collections(db: &Connection) -> Result<HashMap<String, &'static str>, Box<dyn Error>> {
+    let mut collections = HashMap::new();
+    for (collection, sql) in [
+        ("mirc", "SELECT mint_address FROM mirc_mints"),
+        ("mono", "SELECT mint_address FROM mono_mints"),
+    ] {
+        let mut stmt = db.prepare(sql)?;
+        for mint in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            collections.insert(mint?, collection);
+        }
+    }
+    Ok(collections)
+}
+
+/// The creators of either collection that take a share of royalties.
+fn creator_addresses(policy: &RoyaltyPolicy) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+    let mut addresses = vec![];
+    for collection in policy.mirc.iter().chain(policy.mono.iter()) {
+        for creator in &collection.creators {
+            let address: Pubkey = creator.address.parse()?;
+            if creator.share > 0 && !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+/// Successful and failed signatures for `address`, newest first, back to
+/// but not including `until`. Only finalized transactions are listed so
+/// the newest one can be remembered as where the next scan stops.
+async fn fetch_signatures(
+    rpc: &RpcClient,
+    limiter: &RateLimiter,
+    address: &Pubkey,
+    until: Option<Signature>,
+) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, Box<dyn Error>> {
+    let mut signatures = vec![];
+    let mut before = None;
+    loop {
+        limiter.wait().await;
+        let page = rpc.get_signatures_for_address_with_config(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until,
+                limit: None,
+                commitment: Some(CommitmentConfig::finalized()),
+            },
+        )?;
+        match page.last() {
+            Some(last) => before = Some(last.signature.parse()?),
+            None => break,
+        }
+        signatures.extend(page);
+    }
+    Ok(signatures)
+}
+
+struct RoyaltyPayment {
+    mint: String,
+    collection: Option<&'static str>,
+    lamports: u64,
+}
+
+/// What `address` was paid in `tx`, if anything, and for which mint. A
+/// payment in a transaction that moved no tokens isn't a royalty; one that
+/// moved tokens of none of our mints is kept without a collection.
+fn royalty_payment(
+    tx: &EncodedConfirmedTransaction,
+    address: &Pubkey,
+    collections: &HashMap<String, &'static str>,
+) -> Option<RoyaltyPayment> {
+    let meta = tx.transaction.meta.as_ref()?;
+    if meta.err.is_some() {
+        return None;
+    }
+    let message = tx.transaction.transaction.decode()?.message;
+    let index = message.account_keys.iter().position(|key| key == address)?;
+    let lamports = meta
+        .post_balances
+        .get(index)?
+        .checked_sub(*meta.pre_balances.get(index)?)?;
+    if lamports == 0 {
+        return None;
+    }
+
+    let mints: Vec<&String> = meta
+        .pre_token_balances
+        .iter()
+        .chain(meta.post_token_balances.iter())
+        .flatten()
+        .map(|balance| &balance.mint)
+        .collect();
+    let first = mints.first()?;
+    let (mint, collection) = match mints
+        .iter()
+        .find_map(|mint| collections.get(*mint).map(|collection| (mint, collection)))
+    {
+        Some((mint, collection)) => (mint.to_string(), Some(*collection)),
+        None => (first.to_string(), None),
+    };
+
+    Some(RoyaltyPayment {
+        mint,
+        collection,
+        lamports,
+    })
+}
+
+/// Prints royalty income per collection and address since `since`, or
+/// since the last secondary sale distribution.
+fn print_income(db: &Connection, since: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let since = match since {
+        Some(since) => Some(since.to_string()),
+        None => last_secondary_distribution(db)?,
+    };
+
+    let mut stmt = db.prepare(
+        "SELECT coalesce(collection, 'other'), address, sum(lamports), count(*)
+             FROM royalty_payments
+             WHERE ?1 IS NULL OR block_time >= strftime('%s', ?1)
+             GROUP BY 1, 2
+             ORDER BY 1, 2",
+    )?;
+    let rows = stmt
+        .query_map(params![since], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)? as u64,
+                row.get::<_, i64>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match &since {
+        Some(since) => println!("royalty income since {}", since),
+        None => println!("royalty income"),
+    }
+    let mut totals: Vec<(String, u64)> = vec![];
+    for (collection, address, lamports, count) in rows {
+        println!(
+            "  {:<6} {} {:>16} SOL from {} sales",
+            collection,
+            address,
+            lamports_to_sol(lamports),
+            count
+        );
+        match totals.last_mut() {
+            Some((last, total)) if *last == collection => *total += lamports,
+            _ => totals.push((collection, lamports)),
+        }
+    }
+    for (collection, total) in totals {
+        println!("  {:<6} total {} SOL", collection, lamports_to_sol(total));
+    }
+    Ok(())
+}
+
+/// When the last secondary distribution was paid, if there has been one.
+fn last_secondary_distribution(db: &Connection) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(db.query_row(
+        "SELECT max(created_at) FROM distributions WHERE sale_type = 'secondary'",
+        params![],
+        |row| row.get(0),
+    )?)
+}
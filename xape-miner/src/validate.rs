@@ -0,0 +1,190 @@
+//! Checks each mint's off-chain json against the Metaplex token metadata
+//! standard and against what its metadata account says on chain.
+
+use crate::{
+    audit::{print_findings, Finding},
+    fetch_json, fetch_metadata_limited,
+    rate_limit::RateLimiter,
+    ValidateJson,
+};
+use futures::{stream, StreamExt};
+use mpl_token_metadata::state::Metadata;
+use rusqlite::params;
+use serde::Deserialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::error::Error;
+use xapes_core::schema;
+
+/// The parts of the standard json we check. Everything is optional so a
+/// missing field is reported rather than failing the whole document.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct OffChainMetadata {
+    name: Option<String>,
+    symbol: Option<String>,
+    seller_fee_basis_points: Option<u16>,
+    image: Option<String>,
+    properties: Option<Properties>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct Properties {
+    files: Vec<PropertiesFile>,
+    creators: Vec<PropertiesCreator>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct PropertiesFile {
+    uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct PropertiesCreator {
+    address: String,
+    share: u8,
+}
+
+pub async fn validate_json(opts: ValidateJson) -> Result<(), Box<dyn Error>> {
+    let db = schema::open(&opts.db)?;
+    let rpc = RpcClient::new(opts.rpc.clone());
+    let limiter = RateLimiter::new(opts.rps);
+    let client = reqwest::Client::new();
+
+    let collections: &[&str] = match opts.collection.as_deref() {
+        Some("mirc") => &["mirc"],
+        Some("mono") => &["mono"],
+        Some(other) => return Err(format!("unknown collection {:?}", other).into()),
+        None => &["mirc", "mono"],
+    };
+
+    let mut findings = vec![];
+    for &collection in collections {
+        let mut stmt = db.prepare(&format!("SELECT mint_address FROM {}_mints", collection))?;
+        let mints = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|mint| Ok(mint?.parse()?))
+            .collect::<Result<Vec<Pubkey>, Box<dyn Error>>>()?;
+        let metadata = fetch_metadata_limited(&rpc, &mints, &limiter).await?;
+
+        let collection_findings: Vec<Finding> = stream::iter(mints.iter().zip(&metadata))
+            .map(|(mint, metadata)| validate_mint(&client, &limiter, collection, mint, metadata))
+            .buffered(opts.concurrency)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+
+        db.execute(
+            "DELETE FROM json_findings WHERE collection = ?1",
+            params![collection],
+        )?;
+        for finding in &collection_findings {
+            db.execute(
+                "INSERT INTO json_findings
+                (mint_address, collection, check_name, detail, checked_at) values
+                (          ?1,         ?2,         ?3,     ?4, datetime('now'))",
+                params![
+                    finding.mint,
+                    finding.collection,
+                    finding.check,
+                    finding.detail
+                ],
+            )?;
+        }
+        findings.extend(collection_findings);
+    }
+
+    print_findings(&findings);
+    Ok(())
+}
+
+async fn validate_mint(
+    client: &reqwest::Client,
+    limiter: &RateLimiter,
+    collection: &str,
+    mint: &Pubkey,
+    metadata: &Metadata,
+) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut report = |check: &str, detail: String| {
+        findings.push(Finding {
+            collection: collection.to_string(),
+            mint: mint.to_string(),
+            check: check.to_string(),
+            detail,
+        })
+    };
+
+    let json: OffChainMetadata = match fetch_json(client, limiter, &metadata.data.uri).await {
+        Ok(json) => json,
+        Err(e) => {
+            report("json", format!("{}: {}", metadata.data.uri, e));
+            return findings;
+        }
+    };
+
+    let data = &metadata.data;
+    if json.name.as_deref() != Some(data.name.as_str()) {
+        report(
+            "name",
+            format!("{:?} but on chain {:?}", json.name, data.name),
+        );
+    }
+    if json.symbol.as_deref() != Some(data.symbol.as_str()) {
+        report(
+            "symbol",
+            format!("{:?} but on chain {:?}", json.symbol, data.symbol),
+        );
+    }
+    if json.seller_fee_basis_points != Some(data.seller_fee_basis_points) {
+        report(
+            "seller_fee",
+            format!(
+                "{:?} but on chain {}",
+                json.seller_fee_basis_points, data.seller_fee_basis_points
+            ),
+        );
+    }
+
+    let properties = json.properties.unwrap_or_default();
+    match &json.image {
+        None => report("image", "missing".to_string()),
+        Some(image) => {
+            if !properties.files.iter().any(|file| &file.uri == image) {
+                report("files", format!("{} not in properties.files", image));
+            }
+            limiter.wait().await;
+            match client.head(image).send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => report(
+                    "image_reachable",
+                    format!("{} {}", image, response.status()),
+                ),
+                Err(e) => report("image_reachable", format!("{} {}", image, e)),
+            }
+        }
+    }
+
+    let actual: Vec<(String, u8)> = properties
+        .creators
+        .iter()
+        .map(|c| (c.address.clone(), c.share))
+        .collect();
+    let expected: Vec<(String, u8)> = data
+        .creators
+        .iter()
+        .flatten()
+        .map(|c| (c.address.to_string(), c.share))
+        .collect();
+    if actual != expected {
+        report(
+            "creators",
+            format!("{:?} but on chain {:?}", actual, expected),
+        );
+    }
+
+    findings
+}
@@ -0,0 +1,102 @@
+//! Instructions for the metaplex token entangler program, and decoding its
+//! entangled pair accounts.
+
+use crate::pda::{
+    find_edition_address, find_entangled_escrow_address, find_entangled_pair_address,
+    find_metadata_address,
+};
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, system_program, sysvar};
+use spl_associated_token_account::get_associated_token_address;
+use std::error::Error;
+
+pub use metaplex_token_entangler::EntangledPair;
+
+/// Offset of the authority in an entangled pair account: the account
+/// discriminator, then the treasury mint, both mints and both escrows.
+pub const ENTANGLED_PAIR_AUTHORITY_OFFSET: usize = 8 + 32 * 5;
+
+pub fn program_id() -> Pubkey {
+    metaplex_token_entangler::id()
+}
+
+#[derive(Clone, Debug)]
+pub struct EntanglementTerms {
+    pub authority: Pubkey,
+    pub treasury_mint: Pubkey,
+    pub price: u64,
+    pub pays_every_time: bool,
+}
+
+/// Builds the instructions that entangle `mint_a` with `mint_b`.
+///
+/// `mint_b` is moved out of the payer's associated token account into its
+/// escrow, so `transfer_authority` is approved for that single token for the
+/// duration of the transaction and must sign it alongside the payer.
+pub fn create_entangled_pair_instructions(
+    payer: &Pubkey,
+    transfer_authority: &Pubkey,
+    mint_a: Pubkey,
+    mint_b: Pubkey,
+    terms: &EntanglementTerms,
+) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let (entangled_pair, bump) = find_entangled_pair_address(mint_a, mint_b);
+    let (reverse_entangled_pair, reverse_bump) = find_entangled_pair_address(mint_b, mint_a);
+    let (token_a_escrow, token_a_escrow_bump) =
+        find_entangled_escrow_address(mint_a, mint_b, metaplex_token_entangler::A);
+    let (token_b_escrow, token_b_escrow_bump) =
+        find_entangled_escrow_address(mint_a, mint_b, metaplex_token_entangler::B);
+    let token_b = get_associated_token_address(payer, &mint_b);
+
+    let accounts = metaplex_token_entangler::accounts::CreateEntangledPair {
+        treasury_mint: terms.treasury_mint,
+        payer: *payer,
+        transfer_authority: *transfer_authority,
+        authority: terms.authority,
+        mint_a,
+        metadata_a: find_metadata_address(mint_a),
+        edition_a: find_edition_address(mint_a),
+        mint_b,
+        metadata_b: find_metadata_address(mint_b),
+        edition_b: find_edition_address(mint_b),
+        token_b,
+        token_a_escrow,
+        token_b_escrow,
+        entangled_pair,
+        reverse_entangled_pair,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    };
+
+    let data = metaplex_token_entangler::instruction::CreateEntangledPair {
+        bump,
+        reverse_bump,
+        token_a_escrow_bump,
+        token_b_escrow_bump,
+        price: terms.price,
+        pays_every_time: terms.pays_every_time,
+    };
+
+    Ok(vec![
+        spl_token::instruction::approve(
+            &spl_token::id(),
+            &token_b,
+            transfer_authority,
+            payer,
+            &[],
+            1,
+        )?,
+        Instruction {
+            program_id: metaplex_token_entangler::id(),
+            accounts: accounts.to_account_metas(None),
+            data: data.data(),
+        },
+        spl_token::instruction::revoke(&spl_token::id(), &token_b, payer, &[])?,
+    ])
+}
+
+/// Decodes an entangled pair account, checking its discriminator.
+pub fn decode_entangled_pair(mut data: &[u8]) -> Result<EntangledPair, Box<dyn Error>> {
+    Ok(EntangledPair::try_deserialize(&mut data)?)
+}
@@ -0,0 +1,48 @@
+//! Finding who holds a one-of-one mint right now.
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{account::ReadableAccount, program_pack::Pack, pubkey::Pubkey};
+use spl_token::state::Account;
+use std::error::Error;
+
+#[derive(Clone, Debug)]
+pub struct Holder {
+    pub token_account: Pubkey,
+    pub owner: Pubkey,
+    pub delegate: Option<Pubkey>,
+    pub frozen: bool,
+    /// The slot the token account was read at.
+    pub slot: u64,
+}
+
+/// The token account holding the one token of `mint`, or `None` if no
+/// account holds it, as when it has been burned. Makes two requests: the
+/// mint's largest accounts, then the one holding the token.
+pub fn find_holder(rpc: &RpcClient, mint: &Pubkey) -> Result<Option<Holder>, Box<dyn Error>> {
+    let largest = rpc.get_token_largest_accounts(mint)?;
+    let token_account: Pubkey = match largest.iter().find(|a| a.amount.amount == "1") {
+        Some(balance) => balance.address.parse()?,
+        None => return Ok(None),
+    };
+
+    let response = rpc.get_account_with_commitment(&token_account, rpc.commitment())?;
+    let account = response
+        .value
+        .ok_or_else(|| format!("token account {} of {} is gone", token_account, mint))?;
+    let state = Account::unpack(account.data())?;
+    if state.mint != *mint || state.amount != 1 {
+        return Err(format!(
+            "token account {} no longer holds {}, try again",
+            token_account, mint
+        )
+        .into());
+    }
+
+    Ok(Some(Holder {
+        token_account,
+        owner: state.owner,
+        delegate: state.delegate.into(),
+        frozen: state.is_frozen(),
+        slot: response.context.slot,
+    }))
+}
@@ -0,0 +1,417 @@
+//! A record in sqlite of every action a batch command plans and what came
+//! of it, so an interrupted batch can be run again without repeating work.
+//!
+//! A job is named by the command that runs it (overridable with `--job`),
+//! and each item in it by whatever the command acts on, usually a mint.
+//! Items that were confirmed, or found to need no change, are skipped on
+//! later runs of the same job; failed items are retried.
+//!
+//! An item is marked sent, with its signature and blockhash, just before
+//! its transaction goes out. If the run dies before confirmation, or can't
+//! find out whether it landed, the item stays sent and `resolve_sent`
+//! settles it on the next run by asking the cluster what became of the
+//! signature. A sent item is only marked failed once the transaction is
+//! known to have failed, or to have expired without being processed.
+
+use crate::sender::{Sender, Sent, SignatureStatus};
+use rusqlite::{params, Connection, OptionalExtension};
+use solana_sdk::{hash::Hash, signature::Signature};
+use std::error::Error;
+
+pub const PLANNED: &str = "planned";
+pub const SENT: &str = "sent";
+pub const CONFIRMED: &str = "confirmed";
+pub const UNCHANGED: &str = "unchanged";
+pub const FAILED: &str = "failed";
+
+/// What happened when an item was carried out.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// Chain already matched, nothing was sent.
+    Unchanged,
+    /// Dry run; the transaction was simulated but not sent.
+    Simulated,
+    Confirmed(Signature),
+    /// Sent, but whether it landed is unknown; left for `resolve_sent`.
+    Unresolved(Signature, String),
+}
+
+impl From<Sent> for Outcome {
+    fn from(sent: Sent) -> Self {
+        match sent {
+            Sent::Simulated => Outcome::Simulated,
+            Sent::Confirmed(sig) => Outcome::Confirmed(sig),
+            Sent::Unresolved(sig, reason) => Outcome::Unresolved(sig, reason),
+        }
+    }
+}
+
+/// How a sent item settled: confirmed, or the error it failed with.
+type Settled = Result<Outcome, Box<dyn Error>>;
+
+pub struct Journal<'a> {
+    db: &'a Connection,
+    job: String,
+}
+
+impl<'a> Journal<'a> {
+    pub fn open(db: &'a Connection, job: &str) -> Result<Self, Box<dyn Error>> {
+        db.execute(
+            "INSERT INTO jobs (name, created_at, updated_at)
+                 VALUES (?1, datetime('now'), datetime('now'))
+                 ON CONFLICT (name) DO UPDATE SET updated_at = datetime('now')",
+            params![job],
+        )?;
+        Ok(Journal {
+            db,
+            job: job.to_string(),
+        })
+    }
+
+    pub fn status(&self, item: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self
+            .db
+            .query_row(
+                "SELECT status FROM job_items WHERE job_name = ?1 AND item_key = ?2",
+                params![self.job, item],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// True when an earlier run confirmed the item or found nothing to do,
+    /// or sent it recently enough that it may still land.
+    pub fn is_done(&self, item: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(matches!(
+            self.status(item)?.as_deref(),
+            Some(CONFIRMED) | Some(UNCHANGED) | Some(SENT)
+        ))
+    }
+
+    /// Records the signature of a transaction that is being sent, and the
+    /// blockhash it was signed with, before anyone knows whether it lands.
+    pub fn sent(
+        &self,
+        item: &str,
+        sig: &Signature,
+        blockhash: &Hash,
+    ) -> Result<(), Box<dyn Error>> {
+        self.db.execute(
+            "UPDATE job_items
+                 SET status = ?3, signature = ?4, blockhash = ?5, error = NULL,
+                     updated_at = datetime('now')
+                 WHERE job_name = ?1 AND item_key = ?2",
+            params![self.job, item, SENT, sig.to_string(), blockhash.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Settles items a previous run sent but never saw confirmed. An item
+    /// whose transaction can't be found is only marked failed, and so
+    /// retried, once its blockhash has expired; items sent before
+    /// blockhashes were journaled count as expired two minutes after they
+    /// were sent. Anything the cluster can't answer for is left sent.
+    pub fn resolve_sent(&self, sender: &Sender) -> Result<(), Box<dyn Error>> {
+        let mut stmt = self.db.prepare(
+            "SELECT item_key, signature, blockhash,
+                    updated_at < datetime('now', '-2 minutes')
+                 FROM job_items
+                 WHERE job_name = ?1 AND status = ?2",
+        )?;
+        let sent = stmt
+            .query_map(params![self.job, SENT], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, bool>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (item, sig, blockhash, old) in sent {
+            let sig: Signature = sig.parse()?;
+            let outcome = match self.settle(sender, &sig, blockhash.as_deref(), old) {
+                Ok(Some(outcome)) => outcome,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("{} {} left unresolved: {}", item, sig, e);
+                    continue;
+                }
+            };
+            self.record_status(&item, &outcome, 0)?;
+        }
+        Ok(())
+    }
+
+    /// What became of `sig`, or `None` while it may still land.
+    fn settle(
+        &self,
+        sender: &Sender,
+        sig: &Signature,
+        blockhash: Option<&str>,
+        old: bool,
+    ) -> Result<Option<Settled>, Box<dyn Error>> {
+        match sender.signature_status(sig)? {
+            SignatureStatus::Confirmed => Ok(Some(Ok(Outcome::Confirmed(*sig)))),
+            SignatureStatus::Failed(e) => Ok(Some(Err(format!("{} failed: {}", sig, e).into()))),
+            SignatureStatus::Pending => Ok(None),
+            SignatureStatus::NotFound => {
+                let expired = match blockhash {
+                    Some(blockhash) => !sender.is_blockhash_valid(&blockhash.parse()?)?,
+                    None => old,
+                };
+                if !expired {
+                    return Ok(None);
+                }
+                // it may have landed just before the blockhash expired
+                match sender.signature_status(sig)? {
+                    SignatureStatus::NotFound => Ok(Some(Err(format!(
+                        "{} expired without being processed",
+                        sig
+                    )
+                    .into()))),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Records that `action` is about to be carried out for `item`.
+    pub fn plan(&self, item: &str, action: &str) -> Result<(), Box<dyn Error>> {
+        self.db.execute(
+            "INSERT INTO job_items (job_name, item_key, action, status, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, datetime('now'))
+                 ON CONFLICT (job_name, item_key) DO UPDATE
+                 SET action = ?3, updated_at = datetime('now')",
+            params![self.job, item, action, PLANNED],
+        )?;
+        Ok(())
+    }
+
+    /// Records how `item` turned out. Simulated items are left as planned.
+    pub fn record(
+        &self,
+        item: &str,
+        outcome: &Result<Outcome, Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.record_status(item, outcome, 1)
+    }
+
+    fn record_status(
+        &self,
+        item: &str,
+        outcome: &Result<Outcome, Box<dyn Error>>,
+        attempts: i64,
+    ) -> Result<(), Box<dyn Error>> {
+        let (status, signature, error) = match outcome {
+            Ok(Outcome::Simulated) => return Ok(()),
+            Ok(Outcome::Unchanged) => (UNCHANGED, None, None),
+            Ok(Outcome::Confirmed(sig)) => (CONFIRMED, Some(sig.to_string()), None),
+            Ok(Outcome::Unresolved(sig, reason)) => {
+                (SENT, Some(sig.to_string()), Some(reason.clone()))
+            }
+            Err(e) => (FAILED, None, Some(e.to_string())),
+        };
+        // a failed item keeps the signature of its last attempt, if any
+        self.db.execute(
+            "UPDATE job_items
+                 SET status = ?3, signature = coalesce(?4, signature), error = ?5,
+                     attempts = attempts + ?6, updated_at = datetime('now')
+                 WHERE job_name = ?1 AND item_key = ?2",
+            params![self.job, item, status, signature, error, attempts],
+        )?;
+        Ok(())
+    }
+
+    /// Prints how many items are in each state, and fails if any failed so
+    /// the batch exits non-zero and can be re-run.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        let mut stmt = self.db.prepare(
+            "SELECT status, count(*) FROM job_items
+                 WHERE job_name = ?1
+                 GROUP BY status
+                 ORDER BY status",
+        )?;
+        let counts = stmt
+            .query_map(params![self.job], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut failed = 0;
+        for (status, count) in counts {
+            eprintln!("{} {}: {}", self.job, status, count);
+            if status == FAILED {
+                failed = count;
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} items failed in job {}", failed, self.job).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use solana_client::rpc_client::RpcClient;
+
+    fn db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        schema::migrate(&mut db).unwrap();
+        db
+    }
+
+    fn item(db: &Connection, item: &str) -> (String, Option<String>, Option<String>, i64) {
+        db.query_row(
+            "SELECT status, signature, blockhash, attempts FROM job_items
+                 WHERE job_name = 'test' AND item_key = ?1",
+            params![item],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn planned_sent_confirmed() {
+        let db = db();
+        let journal = Journal::open(&db, "test").unwrap();
+        let (sig, blockhash) = (Signature::new_unique(), Hash::new_unique());
+
+        journal.plan("a", "set uri").unwrap();
+        assert_eq!(journal.status("a").unwrap().as_deref(), Some(PLANNED));
+        assert!(!journal.is_done("a").unwrap());
+
+        journal.sent("a", &sig, &blockhash).unwrap();
+        assert_eq!(
+            item(&db, "a"),
+            (
+                SENT.to_string(),
+                Some(sig.to_string()),
+                Some(blockhash.to_string()),
+                0
+            )
+        );
+        assert!(journal.is_done("a").unwrap());
+
+        journal.record("a", &Ok(Outcome::Confirmed(sig))).unwrap();
+        assert_eq!(item(&db, "a").0, CONFIRMED);
+        assert_eq!(item(&db, "a").1, Some(sig.to_string()));
+        assert_eq!(item(&db, "a").3, 1);
+        journal.finish().unwrap();
+    }
+
+    #[test]
+    fn failed_items_are_retried() {
+        let db = db();
+        let journal = Journal::open(&db, "test").unwrap();
+        let first = Signature::new_unique();
+
+        journal.plan("a", "refund").unwrap();
+        journal.sent("a", &first, &Hash::new_unique()).unwrap();
+        journal
+            .record("a", &Err("a failed: insufficient funds".into()))
+            .unwrap();
+        let (status, signature, _, attempts) = item(&db, "a");
+        assert_eq!(status, FAILED);
+        assert_eq!(signature, Some(first.to_string()));
+        assert_eq!(attempts, 1);
+        assert!(!journal.is_done("a").unwrap());
+        assert!(journal.finish().is_err());
+
+        let second = Signature::new_unique();
+        journal.plan("a", "refund").unwrap();
+        journal.sent("a", &second, &Hash::new_unique()).unwrap();
+        journal
+            .record("a", &Ok(Outcome::Confirmed(second)))
+            .unwrap();
+        let (status, signature, _, attempts) = item(&db, "a");
+        assert_eq!(status, CONFIRMED);
+        assert_eq!(signature, Some(second.to_string()));
+        assert_eq!(attempts, 2);
+        journal.finish().unwrap();
+    }
+
+    #[test]
+    fn unchanged_and_simulated_items() {
+        let db = db();
+        let journal = Journal::open(&db, "test").unwrap();
+
+        journal.plan("a", "set uri").unwrap();
+        journal.record("a", &Ok(Outcome::Unchanged)).unwrap();
+        assert_eq!(journal.status("a").unwrap().as_deref(), Some(UNCHANGED));
+        assert!(journal.is_done("a").unwrap());
+
+        journal.plan("b", "set uri").unwrap();
+        journal.record("b", &Ok(Outcome::Simulated)).unwrap();
+        assert_eq!(journal.status("b").unwrap().as_deref(), Some(PLANNED));
+        assert!(!journal.is_done("b").unwrap());
+    }
+
+    #[test]
+    fn resumes_a_partly_sent_job() {
+        let db = db();
+        let sent = Signature::new_unique();
+        {
+            let journal = Journal::open(&db, "test").unwrap();
+            for key in [
+                "confirmed",
+                "unchanged",
+                "sent",
+                "unresolved",
+                "failed",
+                "planned",
+            ] {
+                journal.plan(key, "transfer").unwrap();
+            }
+            let confirmed = Signature::new_unique();
+            journal
+                .sent("confirmed", &confirmed, &Hash::new_unique())
+                .unwrap();
+            journal
+                .record("confirmed", &Ok(Outcome::Confirmed(confirmed)))
+                .unwrap();
+            journal
+                .record("unchanged", &Ok(Outcome::Unchanged))
+                .unwrap();
+            journal.sent("sent", &sent, &Hash::new_unique()).unwrap();
+            let unresolved = Signature::new_unique();
+            journal
+                .sent("unresolved", &unresolved, &Hash::new_unique())
+                .unwrap();
+            journal
+                .record(
+                    "unresolved",
+                    &Ok(Outcome::Unresolved(unresolved, "timed out".to_string())),
+                )
+                .unwrap();
+            journal.record("failed", &Err("failed".into())).unwrap();
+            // the run dies here, before "planned" is sent
+        }
+
+        let journal = Journal::open(&db, "test").unwrap();
+        let todo: Vec<&str> = [
+            "confirmed",
+            "unchanged",
+            "sent",
+            "unresolved",
+            "failed",
+            "planned",
+        ]
+        .into_iter()
+        .filter(|key| !journal.is_done(key).unwrap())
+        .collect();
+        assert_eq!(todo, ["failed", "planned"]);
+        assert_eq!(item(&db, "unresolved").0, SENT);
+
+        // with no cluster to ask, sent items stay sent
+        let rpc = RpcClient::new("http://127.0.0.1:1".to_string());
+        journal.resolve_sent(&Sender::new(&rpc, false)).unwrap();
+        assert_eq!(item(&db, "sent").0, SENT);
+        assert_eq!(item(&db, "sent").1, Some(sent.to_string()));
+        assert_eq!(item(&db, "unresolved").0, SENT);
+    }
+}
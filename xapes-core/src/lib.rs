@@ -0,0 +1,14 @@
+//! Shared helpers for the xapes command line tools.
+//!
+//! Both `xapes-entangler` and `xape-miner` go through this crate for
+//! metadata decoding, address derivation and sending transactions so the
+//! two binaries can't disagree about how those things work.
+
+pub mod entangler;
+pub mod holders;
+pub mod journal;
+pub mod metadata;
+pub mod pda;
+pub mod policy;
+pub mod schema;
+pub mod sender;
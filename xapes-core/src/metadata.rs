@@ -0,0 +1,86 @@
+//! Fetching and decoding token metadata accounts.
+
+use crate::pda::find_metadata_address;
+use borsh::de::BorshDeserialize;
+use mpl_token_metadata::state::{Creator, Data, Metadata};
+use solana_client::{rpc_client::RpcClient, rpc_request::MAX_MULTIPLE_ACCOUNTS};
+use solana_sdk::{account::ReadableAccount, pubkey::Pubkey};
+use std::error::Error;
+
+/// Fetches and decodes the metadata account for `mint`.
+pub fn fetch_metadata(rpc: &RpcClient, mint: Pubkey) -> Result<Metadata, Box<dyn Error>> {
+    let account = rpc.get_account(&find_metadata_address(mint))?;
+    decode_metadata(account.data())
+}
+
+/// Fetches metadata for many mints, a hundred accounts per request. Mints
+/// without a metadata account come back as `None`.
+pub fn fetch_metadata_batch(
+    rpc: &RpcClient,
+    mints: &[Pubkey],
+) -> Result<Vec<Option<Metadata>>, Box<dyn Error>> {
+    let addresses: Vec<Pubkey> = mints.iter().map(|m| find_metadata_address(*m)).collect();
+    let mut metadata = Vec::with_capacity(addresses.len());
+    for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        for account in rpc.get_multiple_accounts(chunk)? {
+            metadata.push(match account {
+                Some(account) => Some(decode_metadata(account.data())?),
+                None => None,
+            });
+        }
+    }
+    Ok(metadata)
+}
+
+/// Decodes a metadata account, stripping the null padding the program
+/// leaves on name, symbol and uri.
+pub fn decode_metadata(mut data: &[u8]) -> Result<Metadata, Box<dyn Error>> {
+    let mut metadata = Metadata::deserialize(&mut data)?;
+    metadata.data.name = trim_padding(&metadata.data.name);
+    metadata.data.symbol = trim_padding(&metadata.data.symbol);
+    metadata.data.uri = trim_padding(&metadata.data.uri);
+    Ok(metadata)
+}
+
+pub fn trim_padding(s: &str) -> String {
+    s.trim_matches(char::from(0)).to_string()
+}
+
+/// Prints the fields of `before` that `after` changes, one line per field.
+pub fn print_data_diff(mint: &Pubkey, before: &Data, after: &Data) {
+    eprintln!("{}", mint);
+    if before.name != after.name {
+        eprintln!("  name: {:?} -> {:?}", before.name, after.name);
+    }
+    if before.symbol != after.symbol {
+        eprintln!("  symbol: {:?} -> {:?}", before.symbol, after.symbol);
+    }
+    if before.uri != after.uri {
+        eprintln!("  uri: {} -> {}", before.uri, after.uri);
+    }
+    if before.seller_fee_basis_points != after.seller_fee_basis_points {
+        eprintln!(
+            "  seller_fee_basis_points: {} -> {}",
+            before.seller_fee_basis_points, after.seller_fee_basis_points
+        );
+    }
+    if before.creators != after.creators {
+        eprintln!("  creators:");
+        eprintln!("    - {}", format_creators(&before.creators));
+        eprintln!("    + {}", format_creators(&after.creators));
+    }
+}
+
+fn format_creators(creators: &Option<Vec<Creator>>) -> String {
+    match creators {
+        None => "none".to_string(),
+        Some(creators) => creators
+            .iter()
+            .map(|c| {
+                let verified = if c.verified { " verified" } else { "" };
+                format!("{} {}%{}", c.address, c.share, verified)
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
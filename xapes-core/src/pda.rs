@@ -0,0 +1,53 @@
+//! Program derived addresses for token metadata and token entangler accounts.
+
+use solana_sdk::pubkey::Pubkey;
+
+pub fn find_metadata_address(mint: Pubkey) -> Pubkey {
+    let (address, _bump) = Pubkey::find_program_address(
+        &[
+            mpl_token_metadata::state::PREFIX.as_bytes(),
+            mpl_token_metadata::id().as_ref(),
+            mint.as_ref(),
+        ],
+        &mpl_token_metadata::id(),
+    );
+    address
+}
+
+pub fn find_edition_address(mint: Pubkey) -> Pubkey {
+    let (address, _bump) = Pubkey::find_program_address(
+        &[
+            mpl_token_metadata::state::PREFIX.as_bytes(),
+            mpl_token_metadata::id().as_ref(),
+            mint.as_ref(),
+            mpl_token_metadata::state::EDITION.as_bytes(),
+        ],
+        &mpl_token_metadata::id(),
+    );
+    address
+}
+
+pub fn find_entangled_pair_address(mint_a: Pubkey, mint_b: Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            metaplex_token_entangler::PREFIX.as_bytes(),
+            mint_a.as_ref(),
+            mint_b.as_ref(),
+        ],
+        &metaplex_token_entangler::id(),
+    )
+}
+
+/// `side` is `metaplex_token_entangler::A` or `metaplex_token_entangler::B`.
+pub fn find_entangled_escrow_address(mint_a: Pubkey, mint_b: Pubkey, side: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            metaplex_token_entangler::PREFIX.as_bytes(),
+            mint_a.as_ref(),
+            mint_b.as_ref(),
+            metaplex_token_entangler::ESCROW.as_bytes(),
+            side.as_bytes(),
+        ],
+        &metaplex_token_entangler::id(),
+    )
+}
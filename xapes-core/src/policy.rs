@@ -0,0 +1,236 @@
+//! Royalty policy: the creators, shares and seller fee each collection
+//! should carry, and the token metadata constraints any update must meet.
+
+use mpl_token_metadata::state::{
+    Data, MAX_CREATOR_LIMIT, MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH,
+};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashSet, error::Error, fs};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoyaltyPolicy {
+    pub mirc: Option<CollectionPolicy>,
+    pub mono: Option<CollectionPolicy>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CollectionPolicy {
+    pub seller_fee_basis_points: u16,
+    pub creators: Vec<CreatorShare>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreatorShare {
+    pub address: String,
+    pub share: u8,
+}
+
+impl RoyaltyPolicy {
+    /// Reads a policy from toml and validates every collection in it.
+    pub fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let policy: RoyaltyPolicy = toml::from_str(&fs::read_to_string(path)?)?;
+        if let Some(mirc) = &policy.mirc {
+            mirc.validate().map_err(|e| format!("mirc policy: {}", e))?;
+        }
+        if let Some(mono) = &policy.mono {
+            mono.validate().map_err(|e| format!("mono policy: {}", e))?;
+        }
+        Ok(policy)
+    }
+}
+
+impl CollectionPolicy {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut creators = vec![];
+        for creator in &self.creators {
+            let address: Pubkey = creator
+                .address
+                .parse()
+                .map_err(|_| format!("creator {:?} is not a pubkey", creator.address))?;
+            creators.push((address, creator.share));
+        }
+        validate_creators(&creators)?;
+        validate_seller_fee(self.seller_fee_basis_points)
+    }
+}
+
+/// Checks `data` against the limits the token metadata program enforces,
+/// so a bad update fails here instead of on chain.
+pub fn validate_data(data: &Data) -> Result<(), Box<dyn Error>> {
+    if data.name.len() > MAX_NAME_LENGTH {
+        return Err(format!("name {:?} is longer than {}", data.name, MAX_NAME_LENGTH).into());
+    }
+    if data.symbol.len() > MAX_SYMBOL_LENGTH {
+        return Err(format!(
+            "symbol {:?} is longer than {}",
+            data.symbol, MAX_SYMBOL_LENGTH
+        )
+        .into());
+    }
+    if data.uri.len() > MAX_URI_LENGTH {
+        return Err(format!("uri {:?} is longer than {}", data.uri, MAX_URI_LENGTH).into());
+    }
+    validate_seller_fee(data.seller_fee_basis_points)?;
+    if let Some(creators) = &data.creators {
+        let creators: Vec<_> = creators.iter().map(|c| (c.address, c.share)).collect();
+        validate_creators(&creators)?;
+    }
+    Ok(())
+}
+
+fn validate_creators(creators: &[(Pubkey, u8)]) -> Result<(), Box<dyn Error>> {
+    if creators.is_empty() {
+        return Err("no creators".into());
+    }
+    if creators.len() > MAX_CREATOR_LIMIT {
+        return Err(format!(
+            "{} creators, at most {} allowed",
+            creators.len(),
+            MAX_CREATOR_LIMIT
+        )
+        .into());
+    }
+
+    let mut seen = HashSet::new();
+    for (address, _) in creators {
+        if !seen.insert(address) {
+            return Err(format!("creator {} is listed twice", address).into());
+        }
+    }
+
+    let total: u32 = creators.iter().map(|(_, share)| *share as u32).sum();
+    if total != 100 {
+        return Err(format!("creator shares add up to {}, not 100", total).into());
+    }
+    Ok(())
+}
+
+fn validate_seller_fee(seller_fee_basis_points: u16) -> Result<(), Box<dyn Error>> {
+    if seller_fee_basis_points > 10000 {
+        return Err(format!(
+            "seller_fee_basis_points {} is more than 10000",
+            seller_fee_basis_points
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpl_token_metadata::state::Creator;
+    use std::path::Path;
+
+    fn creators(shares: &[u8]) -> Vec<Creator> {
+        shares
+            .iter()
+            .map(|share| Creator {
+                address: Pubkey::new_unique(),
+                verified: false,
+                share: *share,
+            })
+            .collect()
+    }
+
+    fn data(shares: &[u8]) -> Data {
+        Data {
+            name: "MIRC #1".to_string(),
+            symbol: "MIRC".to_string(),
+            uri: "https://arweave.net/mirc".to_string(),
+            seller_fee_basis_points: 500,
+            creators: Some(creators(shares)),
+        }
+    }
+
+    #[test]
+    fn repo_policy_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../policy.toml");
+        let policy = RoyaltyPolicy::read(path.to_str().unwrap()).unwrap();
+        let mirc = policy.mirc.unwrap();
+        assert_eq!(mirc.seller_fee_basis_points, 500);
+        assert_eq!(mirc.creators.len(), 5);
+    }
+
+    #[test]
+    fn accepts_valid_data() {
+        validate_data(&data(&[0, 65, 25, 5, 5])).unwrap();
+        validate_data(&Data {
+            creators: None,
+            ..data(&[])
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_shares_that_dont_add_up_to_100() {
+        assert!(validate_data(&data(&[65, 25, 5])).is_err());
+        assert!(validate_data(&data(&[65, 25, 5, 5, 5])).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_or_no_creators() {
+        assert!(validate_data(&data(&[])).is_err());
+        assert!(validate_data(&data(&[50, 10, 10, 10, 10, 10])).is_err());
+    }
+
+    #[test]
+    fn rejects_a_creator_listed_twice() {
+        let mut data = data(&[50, 50]);
+        let creators = data.creators.as_mut().unwrap();
+        creators[1].address = creators[0].address;
+        assert!(validate_data(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_seller_fee_over_10000() {
+        validate_data(&Data {
+            seller_fee_basis_points: 10000,
+            ..data(&[100])
+        })
+        .unwrap();
+        assert!(validate_data(&Data {
+            seller_fee_basis_points: 10001,
+            ..data(&[100])
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_fields_longer_than_the_program_allows() {
+        let long = |len| "x".repeat(len);
+        assert!(validate_data(&Data {
+            name: long(MAX_NAME_LENGTH + 1),
+            ..data(&[100])
+        })
+        .is_err());
+        assert!(validate_data(&Data {
+            symbol: long(MAX_SYMBOL_LENGTH + 1),
+            ..data(&[100])
+        })
+        .is_err());
+        assert!(validate_data(&Data {
+            uri: long(MAX_URI_LENGTH + 1),
+            ..data(&[100])
+        })
+        .is_err());
+        validate_data(&Data {
+            name: long(MAX_NAME_LENGTH),
+            ..data(&[100])
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_policy_creators_that_arent_pubkeys() {
+        let policy = CollectionPolicy {
+            seller_fee_basis_points: 500,
+            creators: vec![CreatorShare {
+                address: "nobody".to_string(),
+                share: 100,
+            }],
+        };
+        assert!(policy.validate().is_err());
+    }
+}
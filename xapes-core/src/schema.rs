@@ -0,0 +1,337 @@
+//! The sqlite schema shared by every tool, as an ordered list of
+//! migrations.
+//!
+//! `schema_version` records each migration applied. Opening a db with
+//! `open` applies whatever it is missing, in order, each in its own
+//! transaction. Migrations are only ever appended; one that has shipped is
+//! never edited, since dbs that already applied it won't run it again.
+//!
+//! The first migration is the schema as it stood when the tools still
+//! created their own tables, written with `IF NOT EXISTS` so dbs made back
+//! then adopt it without losing anything.
+
+use rusqlite::{params, Connection};
+use std::error::Error;
+
+const MIGRATIONS: &[&str] = &[
+    // 1: baseline
+    "CREATE TABLE IF NOT EXISTS mirc_mints (
+         mint_address text primary key,
+         meta_address text unique,
+         meta_name text,
+         meta_uri text,
+         inmate_number text,
+         image_uri text
+     );
+     CREATE TABLE IF NOT EXISTS mono_mints (
+         mint_address text primary key,
+         meta_address text unique,
+         meta_name text,
+         meta_uri text,
+         inmate_number text,
+         image_uri text,
+         genesis_order integer
+     );
+     CREATE TABLE IF NOT EXISTS blanks (
+         mono_mint   text primary key,
+         mirc_name   text unique,
+         mirc_number numeric unique
+     );
+     CREATE TABLE IF NOT EXISTS entanglements (
+         mirc_mint_address text primary key,
+         mono_mint_address text unique
+     );
+     CREATE TABLE IF NOT EXISTS attributes (
+         mint_address text,
+         trait_type   text,
+         value        text
+     );
+     CREATE TABLE IF NOT EXISTS cache_items (
+         cache      text,
+         item_index integer,
+         name       text,
+         link       text,
+         image_link text,
+         on_chain   boolean,
+         primary key (cache, item_index)
+     );
+     CREATE TABLE IF NOT EXISTS json_findings (
+         mint_address text,
+         collection   text,
+         check_name   text,
+         detail       text,
+         checked_at   text
+     );
+     CREATE TABLE IF NOT EXISTS trait_frequencies (
+         collection text,
+         trait_type text,
+         value      text,
+         count      integer,
+         frequency  real
+     );
+     CREATE TABLE IF NOT EXISTS rarity (
+         mint_address       text primary key,
+         collection         text,
+         rarity_rank        integer,
+         rarity_score       real,
+         statistical_rarity real
+     );
+     CREATE TABLE IF NOT EXISTS royalty_payments (
+         signature  text,
+         address    text,
+         mint       text,
+         collection text,
+         lamports   integer,
+         slot       integer,
+         block_time integer,
+         primary key (signature, address)
+     );
+     CREATE TABLE IF NOT EXISTS royalty_scans (
+         address          text primary key,
+         newest_signature text,
+         scanned_at       text
+     );
+     CREATE TABLE IF NOT EXISTS distributions (
+         id                 integer primary key,
+         sale_type          text,
+         source             text,
+         amount_lamports    integer,
+         remainder_lamports integer,
+         remainder_label    text,
+         balance_before     integer,
+         balance_after      integer,
+         origin             text,
+         created_at         text
+     );
+     CREATE TABLE IF NOT EXISTS distribution_transfers (
+         distribution_id integer,
+         position        integer,
+         label           text,
+         recipient       text,
+         lamports        integer,
+         batch           integer,
+         status          text,
+         signature       text,
+         primary key (distribution_id, position)
+     );
+     CREATE TABLE IF NOT EXISTS jobs (
+         name       text primary key,
+         created_at text,
+         updated_at text
+     );
+     CREATE TABLE IF NOT EXISTS job_items (
+         job_name   text,
+         item_key   text,
+         action     text,
+         status     text,
+         signature  text,
+         error      text,
+         attempts   integer default 0,
+         updated_at text,
+         primary key (job_name, item_key)
+     );",
+    // 2: lookups the reports make by mint and collection
+    "CREATE INDEX attributes_mint_address ON attributes (mint_address);
+     CREATE INDEX json_findings_collection ON json_findings (collection);
+     CREATE INDEX trait_frequencies_collection ON trait_frequencies (collection);
+     CREATE INDEX royalty_payments_block_time ON royalty_payments (block_time);",
+    // 3: holder snapshots
+    "CREATE TABLE holder_snapshots (
+         id       integer primary key,
+         name     text unique,
+         taken_at text
+     );
+     CREATE TABLE holders (
+         snapshot_id   integer,
+         mint_address  text,
+         collection    text,
+         token_account text,
+         owner         text,
+         delegate      text,
+         frozen        boolean,
+         slot          integer,
+         primary key (snapshot_id, mint_address)
+     );
+     CREATE INDEX holders_owner ON holders (owner);",
+    // 4: refunds, one per mint so none is paid twice
+    "CREATE TABLE refunds (
+         mint_address text primary key,
+         wallet       text,
+         lamports     integer,
+         status       text,
+         signature    text,
+         updated_at   text
+     );",
+    // 5: the mints each custody wallet is supposed to hold
+    "CREATE TABLE custody_expected (
+         custody      text,
+         mint_address text,
+         primary key (custody, mint_address)
+     );",
+    // 6: entangled pair accounts on chain, linked to the plan by their mirc mint
+    "CREATE TABLE entangled_pairs (
+         address           text primary key,
+         authority         text,
+         treasury_mint     text,
+         mint_a            text,
+         mint_b            text,
+         token_a_escrow    text,
+         token_b_escrow    text,
+         price             integer,
+         paid              boolean,
+         pays_every_time   boolean,
+         mirc_mint_address text,
+         imported_at       text
+     );
+     CREATE INDEX entangled_pairs_mirc_mint_address ON entangled_pairs (mirc_mint_address);",
+    // 7: the blockhash a sent item was signed with, to tell when it expires
+    "ALTER TABLE job_items ADD COLUMN blockhash text;",
+    // 8: rank by statistical rarity, alongside the rank by score
+    "ALTER TABLE rarity ADD COLUMN statistical_rank integer;",
+];
+
+/// Opens the db at `path`, bringing its schema up to date.
+pub fn open(path: &str) -> Result<Connection, Box<dyn Error>> {
+    let mut db = Connection::open(path)?;
+    migrate(&mut db)?;
+    Ok(db)
+}
+
+pub fn migrate(db: &mut Connection) -> Result<(), Box<dyn Error>> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+             version    integer primary key,
+             applied_at text
+        )",
+        params![],
+    )?;
+    let current: i64 = db.query_row(
+        "SELECT coalesce(max(version), 0) FROM schema_version",
+        params![],
+        |row| row.get(0),
+    )?;
+    if current > MIGRATIONS.len() as i64 {
+        return Err(format!(
+            "db is at schema version {} but this build only knows {}",
+            current,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = index as i64 + 1;
+        let tx = db.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?1, datetime('now'))",
+            params![version],
+        )?;
+        tx.commit()?;
+        eprintln!("migrated db to schema version {}", version);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(db: &Connection) -> i64 {
+        db.query_row(
+            "SELECT max(version) FROM schema_version",
+            params![],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    /// A db as the tools left it before migrations: some of the baseline
+    /// tables, with data, and no `schema_version`.
+    fn unversioned_db() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE mirc_mints (
+                 mint_address text primary key,
+                 meta_address text unique,
+                 meta_name text,
+                 meta_uri text,
+                 inmate_number text,
+                 image_uri text
+             );
+             INSERT INTO mirc_mints
+             (mint_address, meta_name, inmate_number) values
+             (      'mint', 'MIRC #7',           '7');
+             CREATE TABLE job_items (
+                 job_name   text,
+                 item_key   text,
+                 action     text,
+                 status     text,
+                 signature  text,
+                 error      text,
+                 attempts   integer default 0,
+                 updated_at text,
+                 primary key (job_name, item_key)
+             );
+             INSERT INTO job_items
+             (  job_name, item_key, status) values
+             ('fix-uris',   'mint', 'sent');",
+        )
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn migrates_an_unversioned_db_keeping_its_rows() {
+        let mut db = unversioned_db();
+        migrate(&mut db).unwrap();
+        assert_eq!(version(&db), MIGRATIONS.len() as i64);
+
+        let name: String = db
+            .query_row(
+                "SELECT meta_name FROM mirc_mints WHERE mint_address = 'mint'",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(name, "MIRC #7");
+        let (status, blockhash): (String, Option<String>) = db
+            .query_row(
+                "SELECT status, blockhash FROM job_items WHERE item_key = 'mint'",
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(status, "sent");
+        assert_eq!(blockhash, None);
+        let holders: i64 = db
+            .query_row("SELECT count(*) FROM holders", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(holders, 0);
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate(&mut db).unwrap();
+        migrate(&mut db).unwrap();
+        let applied: i64 = db
+            .query_row("SELECT count(*) FROM schema_version", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn refuses_a_db_from_a_newer_build() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?1, datetime('now'))",
+            params![MIGRATIONS.len() as i64 + 1],
+        )
+        .unwrap();
+        assert!(migrate(&mut db).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Building, signing and sending transactions.
+//!
+//! Every signature is confirmed to the sender's commitment before `send`
+//! returns. If a transaction's blockhash expires before it lands it is
+//! re-signed with a fresh blockhash and sent again, backing off between
+//! attempts. Re-signing only happens once the old blockhash is no longer
+//! valid, so the earlier transaction can't land as well.
+//!
+//! Signatures are looked up with history search, so a transaction that
+//! landed long ago is still found. A transaction's signature is handed to
+//! the caller before it is sent, so it is on record even if the send
+//! itself times out. From then on, an rpc error leaves it unresolved
+//! rather than failed: it may yet land, and must not be sent again until
+//! that is settled. Only a send the cluster turned away, having checked
+//! the transaction before forwarding it, counts as not sent.
+
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::{Transaction, TransactionError},
+};
+use std::{error::Error, thread::sleep, time::Duration};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const BACKOFF: Duration = Duration::from_secs(2);
+
+/// What came of `Sender::send`.
+#[derive(Clone, Debug)]
+pub enum Sent {
+    /// Dry run; the transaction was simulated but not sent.
+    Simulated,
+    Confirmed(Signature),
+    /// Sent, but it couldn't be found out whether it landed.
+    Unresolved(Signature, String),
+}
+
+/// What the cluster knows of a signature.
+#[derive(Clone, Debug)]
+pub enum SignatureStatus {
+    /// Landed and reached the sender's commitment.
+    Confirmed,
+    /// Landed but failed.
+    Failed(TransactionError),
+    /// Processed, not yet at the sender's commitment.
+    Pending,
+    /// Never processed, as far as the ledger history goes.
+    NotFound,
+}
+
+pub struct Sender<'a> {
+    rpc: &'a RpcClient,
+    dry_run: bool,
+    commitment: CommitmentConfig,
+    retries: u32,
+}
+
+impl<'a> Sender<'a> {
+    /// With `dry_run` set every transaction is simulated and nothing is sent.
+    pub fn new(rpc: &'a RpcClient, dry_run: bool) -> Self {
+        Sender {
+            rpc,
+            dry_run,
+            commitment: CommitmentConfig::confirmed(),
+            retries: 5,
+        }
+    }
+
+    pub fn with_commitment(self, commitment: CommitmentConfig) -> Self {
+        Sender { commitment, ..self }
+    }
+
+    /// How many times an expired transaction is re-signed and sent again.
+    pub fn with_retries(self, retries: u32) -> Self {
+        Sender { retries, ..self }
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn commitment(&self) -> CommitmentConfig {
+        self.commitment
+    }
+
+    /// Signs `instructions` with `signers` and sends them as a single
+    /// transaction, waiting for confirmation. The first signer pays.
+    ///
+    /// Returns `Sent::Simulated` in dry run mode, once the simulation has
+    /// succeeded.
+    pub fn send(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<Sent, Box<dyn Error>> {
+        self.send_tracked(instructions, signers, |_, _| Ok(()))
+    }
+
+    /// Like `send`, calling `on_signed` with each signature and the
+    /// blockhash it was signed with just before it is sent.
+    pub fn send_tracked<F>(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+        mut on_signed: F,
+    ) -> Result<Sent, Box<dyn Error>>
+    where
+        F: FnMut(&Signature, &Hash) -> Result<(), Box<dyn Error>>,
+    {
+        let payer = signers.first().ok_or("no signers")?.pubkey();
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                sleep(BACKOFF * 2u32.pow(attempt - 1));
+            }
+
+            let recent_blockhash = self.rpc.get_latest_blockhash()?;
+            let tx = Transaction::new_signed_with_payer(
+                instructions,
+                Some(&payer),
+                signers,
+                recent_blockhash,
+            );
+
+            if self.dry_run {
+                self.simulate(&tx)?;
+                return Ok(Sent::Simulated);
+            }
+
+            let sig = tx.signatures[0];
+            on_signed(&sig, &recent_blockhash)?;
+            match self.rpc.send_transaction(&tx) {
+                Ok(_) => {}
+                Err(e) if is_blockhash_not_found(&e) => {
+                    eprintln!(
+                        "blockhash not found, retrying ({}/{})",
+                        attempt + 1,
+                        self.retries
+                    );
+                    continue;
+                }
+                // preflight turned it away, it was never forwarded
+                Err(e) if e.get_transaction_error().is_some() => return Err(e.into()),
+                // it may have gone out all the same
+                Err(e) => return Ok(Sent::Unresolved(sig, e.to_string())),
+            }
+
+            match self.confirm(&sig, &recent_blockhash) {
+                Ok(SignatureStatus::Confirmed) => return Ok(Sent::Confirmed(sig)),
+                Ok(SignatureStatus::Failed(e)) => {
+                    return Err(format!("{} failed: {}", sig, e).into())
+                }
+                Ok(_) => {}
+                Err(e) => return Ok(Sent::Unresolved(sig, e.to_string())),
+            }
+            eprintln!(
+                "{} expired before it was confirmed, retrying ({}/{})",
+                sig,
+                attempt + 1,
+                self.retries
+            );
+        }
+
+        Err(format!("gave up after {} attempts", self.retries + 1).into())
+    }
+
+    /// Looks `sig` up, searching the ledger history as well as the recent
+    /// status cache.
+    pub fn signature_status(&self, sig: &Signature) -> Result<SignatureStatus, ClientError> {
+        let status = self
+            .rpc
+            .get_signature_statuses_with_history(&[*sig])?
+            .value
+            .into_iter()
+            .next()
+            .flatten();
+        Ok(match status {
+            None => SignatureStatus::NotFound,
+            Some(status) => match status.err {
+                Some(e) => SignatureStatus::Failed(e),
+                None if status.satisfies_commitment(self.commitment) => SignatureStatus::Confirmed,
+                None => SignatureStatus::Pending,
+            },
+        })
+    }
+
+    pub fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, ClientError> {
+        self.rpc
+            .is_blockhash_valid(blockhash, CommitmentConfig::processed())
+    }
+
+    /// Waits for `sig` to reach the sender's commitment or fail. Returns
+    /// `NotFound` once `blockhash` has expired and the transaction hasn't
+    /// been processed.
+    fn confirm(&self, sig: &Signature, blockhash: &Hash) -> Result<SignatureStatus, ClientError> {
+        loop {
+            match self.signature_status(sig)? {
+                SignatureStatus::Pending => {}
+                SignatureStatus::NotFound => {
+                    // look again after the expiry check, it may have landed in between
+                    if !self.is_blockhash_valid(blockhash)? {
+                        match self.signature_status(sig)? {
+                            SignatureStatus::Pending => {}
+                            status => return Ok(status),
+                        }
+                    }
+                }
+                status => return Ok(status),
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn simulate(&self, tx: &Transaction) -> Result<(), Box<dyn Error>> {
+        let res = self.rpc.simulate_transaction(tx)?.value;
+        for log in res.logs.unwrap_or_default() {
+            eprintln!("  {}", log);
+        }
+        match res.err {
+            Some(err) => Err(format!("simulation failed: {}", err).into()),
+            None => {
+                eprintln!("simulation ok, not sent (dry run)");
+                Ok(())
+            }
+        }
+    }
+}
+
+fn is_blockhash_not_found(e: &ClientError) -> bool {
+    e.get_transaction_error() == Some(TransactionError::BlockhashNotFound)
+}